@@ -0,0 +1,363 @@
+use std::{
+    fs,
+    io::{self, Read},
+};
+
+use clap::{Parser, Subcommand};
+use console::style;
+use serde_json::{json, Map, Value};
+
+use crate::services::{
+    backend::{self, EslBackend, UpdateOutcome},
+    build_client,
+    esl_service::{self, HublotPrintRequest},
+    pricer_service::PricerBackend,
+};
+use crate::settings::{secrets, ConfigSources, Settings, DEFAULT_CONFIG_FILE};
+
+custom_error! {
+    /// An error that can occur while reading the command line arguments.
+    ///
+    /// This error can be seamlessly converted to an `io::Error` and `serde_json::Error` via a `From`
+    /// implementation.
+    pub CliError
+        Io{source: io::Error}= "An I/O error occured: {source}",
+        Json{source: serde_json::Error} = "Invalid print request: {source}",
+        Secret{source: secrets::SecretError} = "{source}",
+        Config{source: config::ConfigError} = "Cannot read the configuration: {source}"
+}
+
+/// A small service broker between the Hublot server and the ESL servers
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Cli {
+    /// Path of the base configuration file
+    #[arg(long, global = true, default_value = DEFAULT_CONFIG_FILE)]
+    pub config: String,
+    /// Path of the per-store configuration file, overriding the base one [default: hublot-store.toml when it exists]
+    #[arg(long, global = true)]
+    pub store_config: Option<String>,
+    /// The [profiles.<name>] table to apply, e.g. staging or production
+    #[arg(long, global = true, env = "HUBLOT_PROFILE")]
+    pub profile: Option<String>,
+    /// Disables the logo, the step banners and the progress bars
+    #[arg(long, global = true)]
+    pub headless: bool,
+    /// Shows the interactive dashboard of the print requests in place of the spinner
+    #[arg(long, global = true)]
+    pub dashboard: bool,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Starts the polling loop, the default command
+    Run,
+    /// Validates the configuration, the certificates and the connectivity, then exits
+    Check,
+    /// Sends a single ESL update to the esl server
+    Push {
+        /// The barcode of the label to update
+        #[arg(long)]
+        barcode: String,
+        /// A JSON file holding the print request to display, as served by the Hublot server
+        #[arg(long)]
+        file: String,
+        /// The displayPosition of the item to update on a multi-item label
+        #[arg(long)]
+        position: Option<i32>,
+        /// Updates every item linked to the label
+        #[arg(long)]
+        all_positions: bool,
+    },
+    /// Shows the Pricer items linked to a label
+    Map {
+        /// The barcode of the label
+        barcode: String,
+    },
+    /// Shows the result of a Pricer update request
+    Status {
+        /// The requestId returned by Pricer when the update was accepted
+        request_id: i32,
+    },
+    /// Encrypts a secret with the machine key file, to paste as `enc:...` in the configuration
+    EncryptSecret {
+        /// The secret to encrypt, read from stdin when not set to keep it out of the shell history
+        value: Option<String>,
+    },
+}
+
+impl Cli {
+    /// The configuration layers selected on the command line
+    pub fn config_sources(&self) -> ConfigSources {
+        ConfigSources {
+            config_file: self.config.clone(),
+            store_file: self.store_config.clone(),
+            profile: self.profile.clone(),
+        }
+    }
+}
+
+/// Prints the outcome of a diagnostic step and returns it
+fn report(success: bool, step: &str, detail: String) -> bool {
+    let mark = if success {
+        style("ok").green().bold()
+    } else {
+        style("failed").red().bold()
+    };
+    println!("{mark:>6} {step}: {detail}");
+    success
+}
+
+/// Runs a diagnostic command, returns false when it failed
+pub async fn execute(command: Command, config: &Settings) -> bool {
+    match command {
+        Command::Run | Command::EncryptSecret { .. } => {
+            unreachable!("the polling loop and encrypt-secret are started by main")
+        }
+        Command::Check => check(config).await,
+        Command::Push {
+            barcode,
+            file,
+            position,
+            all_positions,
+        } => match read_request(barcode, file, position, all_positions) {
+            Ok(request) => push(config, &request).await,
+            Err(e) => report(false, "push", e.to_string()),
+        },
+        Command::Map { barcode } => map(config, &barcode).await,
+        Command::Status { request_id } => status(config, request_id).await,
+    }
+}
+
+/// Validates the configuration, the Hublot certificates and the connection to both servers
+pub async fn check(config: &Settings) -> bool {
+    let mut success = match config.validate() {
+        Ok(()) => report(
+            true,
+            "configuration",
+            format!(
+                "esl_vendor={} profile={}",
+                config.esl_vendor.as_deref().unwrap_or(PricerBackend::NAME),
+                config.profile.as_deref().unwrap_or("none")
+            ),
+        ),
+        Err(config_report) => {
+            for problem in config_report.problems {
+                report(false, "configuration", problem);
+            }
+            false
+        }
+    };
+    match build_client(
+        config.proxy_cs.clone(),
+        config.certificate_pem_path.clone(),
+        config.certificate_root_path.clone(),
+        config.certificate_key_path.clone(),
+    ) {
+        Ok(client) => {
+            success &= report(true, "certificates", "the http client is valid".to_string());
+            success &= match esl_service::status(&config.hublot_server_url, &client).await {
+                Ok(true) => report(true, "hublot server", config.hublot_server_url.clone()),
+                Ok(false) => report(
+                    false,
+                    "hublot server",
+                    format!("{} did not answer OK", config.hublot_server_url),
+                ),
+                Err(e) => report(false, "hublot server", e.to_string()),
+            };
+        }
+        Err(e) => success &= report(false, "certificates", e.to_string()),
+    }
+    success &= match backend::health(config).await {
+        Ok(true) => report(true, "esl server", config.esl_server_url.clone()),
+        Ok(false) => report(
+            false,
+            "esl server",
+            format!("{} did not answer OK", config.esl_server_url),
+        ),
+        Err(e) => report(false, "esl server", e.to_string()),
+    };
+    success
+}
+
+/// Builds the print request of `barcode` from the JSON `file`.
+///
+/// The file must hold every key of a print request, the update would otherwise blank the
+/// properties of the label.
+fn read_request(
+    barcode: String,
+    file: String,
+    position: Option<i32>,
+    all_positions: bool,
+) -> Result<HublotPrintRequest, CliError> {
+    let mut request: Map<String, Value> = serde_json::from_str(&fs::read_to_string(file)?)?;
+    request.insert("id".to_string(), json!(barcode));
+    if position.is_some() {
+        request.insert("displayPosition".to_string(), json!(position));
+    }
+    if all_positions {
+        request.insert("allPositions".to_string(), json!(true));
+    }
+    Ok(serde_json::from_value(Value::Object(request))?)
+}
+
+/// Pushes a single print request and prints its outcome
+pub async fn push(config: &Settings, request: &HublotPrintRequest) -> bool {
+    match backend::push(config, request).await {
+        Ok(Ok(report)) => self::report(
+            report.outcome == UpdateOutcome::Succeeded,
+            &format!("push {}", request.id()),
+            format!(
+                "{:?}, request {} is {} {:?}",
+                report.outcome, report.request_id, report.status, report.errors
+            ),
+        ),
+        Ok(Err(e)) | Err(e) => report(false, &format!("push {}", request.id()), e.to_string()),
+    }
+}
+
+/// Prints the Pricer items linked to `barcode`
+pub async fn map(config: &Settings, barcode: &str) -> bool {
+    let backend = match PricerBackend::from_settings(config) {
+        Ok(backend) => backend,
+        Err(e) => return report(false, "map", e.to_string()),
+    };
+    match backend.label(barcode).await {
+        Ok(label) => {
+            report(
+                true,
+                &format!("label {}", label.barcode),
+                format!("{} ({} items)", label.model_name, label.links.len()),
+            );
+            for link in label.links {
+                println!(
+                    "       displayPosition={} itemId={}",
+                    link.display_position, link.item_id
+                );
+            }
+            true
+        }
+        Err(e) => report(false, &format!("label {barcode}"), e.to_string()),
+    }
+}
+
+/// Prints the result of the Pricer update request `request_id`
+pub async fn status(config: &Settings, request_id: i32) -> bool {
+    let backend = match PricerBackend::from_settings(config) {
+        Ok(backend) => backend,
+        Err(e) => return report(false, "status", e.to_string()),
+    };
+    match backend.items_result(request_id).await {
+        Ok(Some(result)) => {
+            let success = report(
+                result.is_successful(),
+                &format!("request {request_id}"),
+                result.status.clone(),
+            );
+            for item in result.item_results {
+                println!(
+                    "       itemId={} status={} {:?}",
+                    item.item_id, item.status, item.errors
+                );
+            }
+            success
+        }
+        Ok(None) => report(
+            false,
+            &format!("request {request_id}"),
+            "unknown to the Pricer server".to_string(),
+        ),
+        Err(e) => report(false, &format!("request {request_id}"), e.to_string()),
+    }
+}
+
+/// Prints `value`, or the first line of stdin, encrypted with the key file of the configuration.
+///
+/// Only `secret_key_file` is read from `sources`, the other keys may still be missing or unresolved.
+pub fn encrypt_secret(sources: &ConfigSources, value: Option<String>) -> bool {
+    let encrypt = || -> Result<String, CliError> {
+        let key_file = sources.secret_key_file()?;
+        let value = match value {
+            Some(value) => value,
+            None => {
+                let mut value = String::new();
+                io::stdin().read_to_string(&mut value)?;
+                value.lines().next().unwrap_or_default().to_string()
+            }
+        };
+        Ok(secrets::encrypt(&value, &key_file)?)
+    };
+    match encrypt() {
+        Ok(encrypted) => {
+            println!("{encrypted}");
+            true
+        }
+        Err(e) => report(false, "encrypt-secret", e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `content` to a temporary print request file, removed once the request is read
+    fn push_request(content: &str, args: &[&str]) -> Result<HublotPrintRequest, CliError> {
+        let path = std::env::temp_dir().join(format!("push-{}.json", uuid::Uuid::new_v4()));
+        fs::write(&path, content).unwrap();
+        let file = path.to_str().unwrap();
+        let cli =
+            Cli::parse_from([&["esl-services-backend", "push", "--file", file], args].concat());
+        let request = match cli.command {
+            Some(Command::Push {
+                barcode,
+                file,
+                position,
+                all_positions,
+            }) => read_request(barcode, file, position, all_positions),
+            command => panic!("expected a push command, got {command:?}"),
+        };
+        fs::remove_file(&path).unwrap();
+        request
+    }
+
+    const PRINT_REQUEST: &str = r#"{
+        "id": "ignored",
+        "nom": "Bar",
+        "nom_scientifique": "Dicentrarchus labrax",
+        "plu": "1234"
+    }"#;
+
+    #[test]
+    fn push_requires_a_file() {
+        let cli = Cli::try_parse_from(["esl-services-backend", "push", "--barcode", "0001"]);
+        assert!(cli.is_err());
+    }
+
+    #[test]
+    fn push_does_not_blank_the_missing_properties() {
+        let request = push_request(r#"{"nom": "Bar"}"#, &["--barcode", "0001"]);
+        assert!(matches!(request, Err(CliError::Json { .. })));
+    }
+
+    #[test]
+    fn push_reads_the_file() {
+        let request = push_request(PRINT_REQUEST, &["--barcode", "0001"]).unwrap();
+        assert_eq!(request.id(), "0001");
+        assert_eq!(request.esl.nom, "Bar");
+        assert_eq!(request.esl.plu, "1234");
+        assert_eq!(request.display_position, None);
+        assert!(!request.all_positions);
+    }
+
+    #[test]
+    fn push_selects_the_display_position() {
+        let request =
+            push_request(PRINT_REQUEST, &["--barcode", "0001", "--position", "2"]).unwrap();
+        assert_eq!(request.display_position, Some(2));
+        let request =
+            push_request(PRINT_REQUEST, &["--barcode", "0001", "--all-positions"]).unwrap();
+        assert!(request.all_positions);
+    }
+}
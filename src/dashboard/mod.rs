@@ -0,0 +1,280 @@
+use std::{io, panic, time::Duration};
+
+use ansi_to_tui::IntoText;
+use chrono::DateTime;
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use tokio::sync::watch;
+use tui::{
+    backend::{Backend, CrosstermBackend},
+    layout::{Constraint, Direction, Layout},
+    style::{Color, Modifier, Style},
+    text::{Span, Spans, Text},
+    widgets::{Block, Borders, Cell, Paragraph, Row, Table, TableState},
+    Frame, Terminal,
+};
+
+use crate::services::{
+    activity::{ActivityState, PrintActivity, StageStatus, ACTIVITY},
+    control::CONTROL,
+    metrics::{LastCall, METRICS},
+};
+use crate::settings::Settings;
+
+/// Time between two refreshes of the dashboard
+const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The logo is only drawn when the terminal has this many rows
+const LOGO_MIN_HEIGHT: u16 = 30;
+
+/// The full-screen view of the polling worker, shown in place of the spinner with `--dashboard`
+struct Dashboard {
+    logo: Text<'static>,
+    vendor: String,
+    table: TableState,
+    /// The feedback of the last key binding
+    message: Option<String>,
+}
+
+/// Runs the dashboard until the quit key is pressed or `shutdown` is set, then restores the terminal.
+///
+/// Blocks the current thread, it is meant to run in `spawn_blocking`.
+pub fn run(config: &Settings, shutdown: &watch::Receiver<bool>) -> io::Result<()> {
+    // a panic anywhere in the broker would otherwise leave the terminal in raw mode
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let _ = disable_raw_mode();
+        let _ = execute!(io::stdout(), LeaveAlternateScreen);
+        default_hook(info);
+    }));
+    enable_raw_mode()?;
+    let mut stdout = io::stdout();
+    execute!(stdout, EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+    let mut dashboard = Dashboard {
+        logo: include_bytes!("../../logo.ansi.txt")
+            .into_text()
+            .unwrap_or_default(),
+        vendor: config.esl_vendor.clone().unwrap_or("pricer".to_string()),
+        table: TableState::default(),
+        message: None,
+    };
+    let result = dashboard.event_loop(&mut terminal, shutdown);
+    // the terminal is restored even when the dashboard has failed
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+    result
+}
+
+impl Dashboard {
+    fn event_loop<B: Backend>(
+        &mut self,
+        terminal: &mut Terminal<B>,
+        shutdown: &watch::Receiver<bool>,
+    ) -> io::Result<()> {
+        loop {
+            if *shutdown.borrow() {
+                return Ok(());
+            }
+            let activities = ACTIVITY.recent();
+            terminal.draw(|frame| self.draw(frame, &activities))?;
+            if !event::poll(REFRESH_INTERVAL)? {
+                continue;
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                // the raw mode swallows the SIGINT of Ctrl-C
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Ok(())
+                }
+                KeyCode::Up | KeyCode::Char('k') => self.select(&activities, -1),
+                KeyCode::Down | KeyCode::Char('j') => self.select(&activities, 1),
+                KeyCode::Char('r') => self.retry(&activities),
+                KeyCode::Char('p') => {
+                    CONTROL.poll_now();
+                    self.message = Some("Polling the Hublot server...".to_string());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn select(&mut self, activities: &[PrintActivity], offset: isize) {
+        if activities.is_empty() {
+            self.table.select(None);
+            return;
+        }
+        let selected = match self.table.selected() {
+            Some(selected) => selected.saturating_add_signed(offset),
+            None => 0,
+        };
+        self.table.select(Some(selected.min(activities.len() - 1)));
+    }
+
+    /// Queues the selected print request for the next poll when its update has failed
+    fn retry(&mut self, activities: &[PrintActivity]) {
+        let Some(activity) = self.table.selected().and_then(|i| activities.get(i)) else {
+            self.message = Some("Select a failed ESL to retry it".to_string());
+            return;
+        };
+        if !activity.state.is_failure() && activity.state != ActivityState::Retrying {
+            self.message = Some(format!(
+                "The update of esl {} has not failed",
+                activity.barcode
+            ));
+            return;
+        }
+        CONTROL.inject(activity.request.clone());
+        self.message = Some(format!("Retrying esl {}...", activity.barcode));
+    }
+
+    fn draw<B: Backend>(&mut self, frame: &mut Frame<B>, activities: &[PrintActivity]) {
+        let show_logo = frame.size().height >= LOGO_MIN_HEIGHT;
+        let logo_height = if show_logo {
+            self.logo.height() as u16
+        } else {
+            0
+        };
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(logo_height),
+                Constraint::Length(3),
+                Constraint::Min(5),
+                Constraint::Length(1),
+            ])
+            .split(frame.size());
+        if show_logo {
+            frame.render_widget(Paragraph::new(self.logo.clone()), chunks[0]);
+        }
+
+        let failures = activities
+            .iter()
+            .filter(|activity| activity.state.is_failure())
+            .count();
+        let status = Spans::from(vec![
+            Span::raw(" Hublot: "),
+            connection(METRICS.last_poll()),
+            Span::raw(format!("   {}: ", self.vendor)),
+            connection(METRICS.last_esl_call()),
+            Span::raw("   Polling: "),
+            if CONTROL.is_paused() {
+                Span::styled("paused", Style::default().fg(Color::Yellow))
+            } else {
+                Span::styled("running", Style::default().fg(Color::Green))
+            },
+            Span::raw("   Failed ESLs: "),
+            Span::styled(
+                failures.to_string(),
+                Style::default().fg(if failures > 0 {
+                    Color::Red
+                } else {
+                    Color::Green
+                }),
+            ),
+        ]);
+        frame.render_widget(
+            Paragraph::new(status).block(Block::default().borders(Borders::ALL).title("Status")),
+            chunks[1],
+        );
+
+        let header = Row::new(vec![
+            "Time", "Barcode", "Map", "Update", "Confirm", "Outcome", "Attempts", "Error",
+        ])
+        .style(Style::default().add_modifier(Modifier::BOLD));
+        let rows = activities.iter().map(|activity| {
+            let done = activity.state != ActivityState::InProgress;
+            Row::new(vec![
+                Cell::from(time(&activity.at)),
+                Cell::from(activity.barcode.clone()),
+                stage(activity.map, done),
+                stage(activity.update, done),
+                stage(activity.confirm, done),
+                outcome(activity.state),
+                Cell::from(activity.attempts.to_string()),
+                Cell::from(activity.error.clone().unwrap_or_default()),
+            ])
+        });
+        let table = Table::new(rows)
+            .header(header)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title("Recent print requests"),
+            )
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+            .widths(&[
+                Constraint::Length(8),
+                Constraint::Length(16),
+                Constraint::Length(7),
+                Constraint::Length(7),
+                Constraint::Length(7),
+                Constraint::Length(11),
+                Constraint::Length(8),
+                Constraint::Min(20),
+            ]);
+        frame.render_stateful_widget(table, chunks[2], &mut self.table);
+
+        let mut footer = vec![Span::styled(
+            " ↑/↓ select  r retry the selected ESL  p poll now  q quit",
+            Style::default().add_modifier(Modifier::DIM),
+        )];
+        if let Some(message) = &self.message {
+            footer.push(Span::raw(format!("   {message}")));
+        }
+        frame.render_widget(Paragraph::new(Spans::from(footer)), chunks[3]);
+    }
+}
+
+/// The local time of an RFC 3339 date
+fn time(at: &str) -> String {
+    DateTime::parse_from_rfc3339(at)
+        .map(|at| at.format("%H:%M:%S").to_string())
+        .unwrap_or_default()
+}
+
+fn connection(last_call: Option<LastCall>) -> Span<'static> {
+    match last_call {
+        None => Span::styled("waiting", Style::default().fg(Color::Yellow)),
+        Some(call) if call.success => Span::styled(
+            format!("connected ({})", time(&call.at)),
+            Style::default().fg(Color::Green),
+        ),
+        Some(call) => Span::styled(
+            format!("unreachable ({})", time(&call.at)),
+            Style::default().fg(Color::Red),
+        ),
+    }
+}
+
+/// A stage that was not reached is pending while the update is in progress, skipped afterwards
+fn stage(status: StageStatus, done: bool) -> Cell<'static> {
+    match status {
+        StageStatus::Waiting if done => Cell::from("-"),
+        StageStatus::Waiting => Cell::from("..."),
+        StageStatus::Succeeded => Cell::from("ok").style(Style::default().fg(Color::Green)),
+        StageStatus::Failed => Cell::from("failed").style(Style::default().fg(Color::Red)),
+    }
+}
+
+fn outcome(state: ActivityState) -> Cell<'static> {
+    let (label, color) = match state {
+        ActivityState::InProgress => ("updating", Color::Cyan),
+        ActivityState::Succeeded => ("succeeded", Color::Green),
+        ActivityState::Rejected => ("rejected", Color::Red),
+        ActivityState::Retrying => ("retrying", Color::Yellow),
+        ActivityState::Postponed => ("postponed", Color::Yellow),
+        ActivityState::Failed => ("failed", Color::Red),
+    };
+    Cell::from(label).style(Style::default().fg(color))
+}
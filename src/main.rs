@@ -1,32 +1,37 @@
-#![feature(async_fn_in_trait)]
 #[macro_use]
 extern crate custom_error;
+mod cli;
+mod dashboard;
 mod services;
 mod settings;
 mod utils;
-use chrono::Local;
+use chrono::{Local, SecondsFormat};
+use clap::Parser;
+use cli::{Cli, Command};
 use console::{style, Emoji, Term};
 use custom_error::{custom_error, Error};
 use env_logger::Env;
 use esl_utils::parse::ParseClient;
 
-use file_rotate::compression::Compression;
-use file_rotate::suffix::{AppendTimestamp, FileLimit};
-use file_rotate::{ContentLimit, FileRotate, TimeFrequency};
-use log::{debug, error};
+use log::{debug, error, info, LevelFilter};
 use reqwest::StatusCode;
 use services::pricer_service::PricerError;
 use services::{build_client, esl_service::EslServiceError, poll::PollingError, ClientError};
-use settings::Settings;
+use settings::{ConfigSources, Settings};
+use utils::{log_file, log_filter, log_format, shutdown};
 
 use std::io::Write;
 use std::{
     io::{self},
     time::Duration,
 };
-use tokio::{task::JoinError, time::sleep};
+use tokio::{
+    sync::watch,
+    task::{JoinError, JoinHandle},
+    time::sleep,
+};
 
-use crate::services::parse_log::ParseLog;
+use crate::services::parse_log::{self, LogShipper, ParseLog};
 
 #[cfg(target_family = "windows")]
 static LOOKING_GLASS: Emoji<'_, '_> = Emoji("🔍  ", "Θ  ");
@@ -56,6 +61,9 @@ macro_rules! LOG_PLACEHOLDER {
     };
 }
 
+/// Time given to the pending Parse log uploads on shutdown
+const PARSE_FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
 custom_error! {
     /// An error that can occur when the lifetime of the App.
     ///
@@ -72,119 +80,176 @@ custom_error! {
         Todo = "TODO: Missing implementation"
 }
 
+/// Prints a startup step banner, or logs it when running headless
+fn report_step(headless: bool, step: &str, emoji: &Emoji, message: String) {
+    if headless {
+        info!("{} {}", step, message);
+    } else {
+        println!("{} {}{}", style(step).bold().dim(), emoji, message);
+    }
+}
+
+/// Waits for SIGINT, SIGTERM or the quit key of the dashboard and returns what stopped the broker.
+///
+/// A failed dashboard does not stop the broker, it keeps running until a signal.
+async fn stop_requested(dashboard: &mut Option<JoinHandle<io::Result<()>>>) -> &'static str {
+    if let Some(task) = dashboard {
+        let quit = tokio::select! {
+            signal = shutdown::signal() => return signal,
+            quit = task => quit,
+        };
+        *dashboard = None;
+        match quit {
+            Ok(Ok(())) => return "the quit key of the dashboard",
+            Ok(Err(e)) => error!("The dashboard has failed: {}", e),
+            Err(e) => error!("The dashboard has panicked: {}", e),
+        }
+    }
+    shutdown::signal().await
+}
+
 /// the background_task that starts the polling worker and updates the display of the ESLs
-async fn polling_worker(config: Settings) -> Result<(), MainError> {
+async fn polling_worker(
+    config: Settings,
+    reload: watch::Receiver<Settings>,
+    shutdown: watch::Receiver<bool>,
+) -> Result<(), MainError> {
     let polling_client = build_client(
-        config.proxy_cs,
-        config.certificate_pem_path,
-        config.certificate_root_path,
-        config.certificate_key_path,
+        config.proxy_cs.clone(),
+        config.certificate_pem_path.clone(),
+        config.certificate_root_path.clone(),
+        config.certificate_key_path.clone(),
     )?;
 
-    services::poll::poll(
-        &config.client_serial,
-        &config.hublot_server_url,
-        &config.esl_server_url,
-        // we already made sure that both useer&password exists
-        config.pricer_user.unwrap(),
-        config.pricer_password.unwrap(),
-        polling_client,
-        config.polling_rate,
-    )
-    .await
-    .map_err(|e| e.into())
+    services::backend::poll_with_backend(&config, polling_client, &reload, &shutdown)
+        .await
+        .map_err(|e| e.into())
 }
 
 #[tokio::main]
 async fn main() -> Result<(), MainError> {
-    let t = Term::stdout();
-    t.clear_screen()?;
-    let app_config = Settings::new()
-        .expect("Cannot parse the configuration file, make sure that it is complete");
-
-    let mut ts = AppendTimestamp::default(FileLimit::Age(chrono::Duration::days(7)));
-    ts.format = "%Y-%d-%m";
+    let cli = Cli::parse();
+    let sources = cli.config_sources();
+    let command = match cli.command {
+        Some(Command::EncryptSecret { value }) => {
+            let success = cli::encrypt_secret(&sources, value);
+            std::process::exit(if success { 0 } else { 1 })
+        }
+        command => command.unwrap_or(Command::Run),
+    };
+    let app_config = match Settings::new(&sources) {
+        Ok(app_config) => app_config,
+        Err(e) => {
+            eprintln!("Cannot read the configuration file {}: {}", cli.config, e);
+            std::process::exit(1)
+        }
+    };
+    match command {
+        Command::Run => {
+            if let Err(report) = app_config.validate() {
+                eprint!("{report}");
+                std::process::exit(1)
+            }
+            let code = run(app_config, cli.headless, cli.dashboard, sources).await?;
+            std::process::exit(code)
+        }
+        command => {
+            // the diagnostic commands only report on the console
+            env_logger::Builder::from_env(Env::default().default_filter_or("warn")).init();
+            let success = cli::execute(command, &app_config).await;
+            std::process::exit(if success { 0 } else { 1 })
+        }
+    }
+}
 
-    let log_path = app_config
-        .clone()
-        .log_file
-        .unwrap_or("hublot-pricer/pricer.log".to_string());
+/// Starts the polling loop, restarting the poller whenever it crashes or the configuration is reloaded.
+///
+/// Returns the exit code of the broker once it has been stopped by SIGINT or SIGTERM: 0 when the
+/// in-flight ESL updates and the Parse logs were drained in time, 1 otherwise.
+async fn run(
+    mut app_config: Settings,
+    headless: bool,
+    dashboard: bool,
+    sources: ConfigSources,
+) -> Result<i32, MainError> {
+    let t = Term::stdout();
+    // under systemd or in a container the spinners and the logo would only fill the logs
+    let headless = headless || app_config.headless.unwrap_or(false) || !t.is_term();
+    // the dashboard takes over the terminal, the banners and the spinner are only logged
+    let dashboard = !headless && (dashboard || app_config.dashboard.unwrap_or(false));
+    let headless = headless || dashboard;
+    app_config.headless = Some(headless);
+    app_config.dashboard = Some(dashboard);
+    if !headless {
+        t.clear_screen()?;
+    }
 
-    let log_file = Box::new(FileRotate::new(
-        log_path,
-        ts,
-        ContentLimit::Time(TimeFrequency::Daily),
-        Compression::None,
-        #[cfg(unix)]
-        None,
-    ));
+    let log_file = log_file::open(&app_config);
     // let log_file = Box::new(File::create("hublot-logs.txt").expect("Can't create log file"));
 
     let log_level = app_config.clone().log_level.unwrap_or("warn".to_string());
-    let envconf = Env::default().default_filter_or(&log_level);
+    // both keys are required by Settings::validate
     let parse_client = ParseClient::new(
-        app_config
-            .clone()
-            .parse_id
-            .expect("Missing parse configuration key: [parse_id]"),
+        app_config.clone().parse_id.unwrap_or_default(),
         None,
-        app_config
-            .clone()
-            .parse_url
-            .expect("Missing parse configuration key: [parse_url]"),
+        app_config.clone().parse_url.unwrap_or_default(),
     );
+    let log_shipper = LogShipper::start(parse_client, &app_config);
+    let shipper = log_shipper.clone();
     let log_config = app_config.clone();
+    let json_logs = app_config.log_format.as_deref() == Some("json");
 
-    env_logger::Builder::from_env(envconf)
+    // the filter is applied by log_filter so that it can be reloaded
+    let logger = env_logger::Builder::new()
+        .filter_level(LevelFilter::Trace)
         .format(move |buf, record| {
-            writeln!(
-                buf,
-                LOG_PLACEHOLDER!(),
-                record.module_path().unwrap_or("unknown"),
-                record.line().unwrap_or(0),
-                Local::now().format("%Y-%m-%dT%H:%M:%S%.3f"),
-                record.level(),
-                record.args()
-            )
+            let now = Local::now();
+            let context = log_format::context(record);
+            if json_logs {
+                // the JSON logs are read by other tools, their timestamp carries its offset
+                let timestamp = now.to_rfc3339_opts(SecondsFormat::Millis, false);
+                writeln!(
+                    buf,
+                    "{}",
+                    log_format::json_line(record, &timestamp, &log_config.client_serial, &context)
+                )
+            } else {
+                let timestamp = now.format("%Y-%m-%dT%H:%M:%S%.3f");
+                writeln!(
+                    buf,
+                    LOG_PLACEHOLDER!(),
+                    record.module_path().unwrap_or("unknown"),
+                    record.line().unwrap_or(0),
+                    timestamp,
+                    record.level(),
+                    record.args()
+                )
+            }
             .expect("Cannot write log to file");
-            if record.target().contains("esl_services_backend") {
-                let parse_client = parse_client.clone();
-                let log_config = log_config.clone();
-                let log = ParseLog {
-                    app: "hublot-esl-backend".to_string(),
-                    level: record.level().to_string(),
-                    message: record.args().to_string(),
-                    serial: log_config.client_serial,
-                };
-                tokio::task::spawn(async move {
-                    parse_client
-                        .clone()
-                        .save("parse/classes/Log".to_string(), log)
-                        .await
-                });
+            if parse_log::is_shipped(record.target()) {
+                let log = ParseLog::new(record, &log_config.client_serial, &context);
+                shipper.send(log);
             }
             Ok(())
         })
         .target(env_logger::Target::Pipe(log_file))
-        .init();
-
-    let logo = include_str!("../logo.ansi.txt");
+        .build();
+    let log_filter = log_filter::init(logger, &log_level).expect("Cannot install the logger");
 
-    println!("{logo}");
+    if !headless {
+        let logo = include_str!("../logo.ansi.txt");
+        println!("{logo}");
+    }
 
-    println!(
-        "{} {}Loading app configuration...",
-        style("[1/4]").bold().dim(),
-        CONFIG
+    report_step(
+        headless,
+        "[1/4]",
+        &CONFIG,
+        "Loading app configuration...".to_string(),
     );
 
-    debug!(
-        "Fetched config from file client_serial={:?} certificate_pem_path={:?} hublot_server_url={:?} log_file={:?}",
-        app_config.client_serial,
-        app_config.certificate_pem_path,
-        app_config.hublot_server_url,
-        app_config.log_file
-    );
+    // the secrets are resolved by now, they must not reach the log file nor Parse
+    debug!("Fetched config from file {:?}", app_config.redacted());
     // test proxy and log
     {
         let app_config = app_config.clone();
@@ -199,50 +264,114 @@ async fn main() -> Result<(), MainError> {
         let apis = vec![insecure_api, secure_api];
 
         for api in apis {
-            let res = client.get(api.clone()).send().await.expect(
-                "Test connection has failed, please make sure that the proxy configuration is correct"
-            );
-            match res.status() {
-                StatusCode::OK => println!(
-                    "{} {} Connection to {:?}, proxy and certificate are valids",
-                    style("[2/4]").bold().dim(),
-                    CONFIG,
-                    api
+            match client.get(api.clone()).send().await.map(|res| res.status()) {
+                Ok(StatusCode::OK) => report_step(
+                    headless,
+                    "[2/4]",
+                    &CONFIG,
+                    format!(" Connection to {:?}, proxy and certificate are valids", api),
+                ),
+                Ok(_) => report_step(
+                    headless,
+                    "[2/4]",
+                    &CONFIG,
+                    format!(" Connection to  {:?} failed, please make sure that the proxy configuration is correct", api),
+                ),
+                Err(e) => report_step(
+                    headless,
+                    "[2/4]",
+                    &CONFIG,
+                    format!(" Connection to  {:?} failed, please make sure that the proxy configuration is correct: {}", api, e),
                 ),
-                _ => println!(
-                    "{} {} Connection to  {:?} failed, please make sure that the proxy configuration is correct",
-                    style("[2/4]").bold().dim(),
-                    CONFIG,
-                    api
-                )
             }
         }
     }
+    match services::backend::health(&app_config).await {
+        Ok(true) => report_step(
+            headless,
+            "[2/4]",
+            &CONFIG,
+            format!(" Connection to {:?} is valid", app_config.esl_server_url),
+        ),
+        _ => report_step(
+            headless,
+            "[2/4]",
+            &CONFIG,
+            format!(
+                " Connection to {:?} failed, please make sure that the esl server is reachable",
+                app_config.esl_server_url
+            ),
+        ),
+    }
+
+    if let Some(addr) = app_config.monitoring_addr.clone() {
+        let app_config = app_config.clone();
+        tokio::task::spawn(async move {
+            if let Err(e) = services::monitoring::serve(&addr, app_config).await {
+                error!("{}", e);
+            }
+        });
+    }
 
-    let spawn_poll = tokio::task::spawn(async move {
+    let (config_sender, mut config_receiver) = watch::channel(app_config.clone());
+    let live_config = config_receiver.clone();
+    if let Some(port) = app_config.admin_port {
+        let live_config = live_config.clone();
+        tokio::task::spawn(async move {
+            if let Err(e) = services::admin::serve(port, live_config).await {
+                error!("{}", e);
+            }
+        });
+    }
+    tokio::task::spawn(settings::reload::watch(sources, config_sender, log_filter));
+    let (shutdown_sender, shutdown) = watch::channel(false);
+    let mut dashboard_task = dashboard.then(|| {
+        let app_config = app_config.clone();
+        let shutdown = shutdown.clone();
+        tokio::task::spawn_blocking(move || dashboard::run(&app_config, &shutdown))
+    });
+
+    let mut spawn_poll = tokio::task::spawn(async move {
         {
-            println!(
-                "{} {}Checking if the config is complete...",
-                style("[3/4]").bold().dim(),
-                LOOKING_GLASS
+            report_step(
+                headless,
+                "[3/4]",
+                &LOOKING_GLASS,
+                "Checking if the config is complete...".to_string(),
             );
-            let app_config = app_config.clone();
-            app_config.pricer_user.expect("Pricer user is empty in the config file, please add 'pricer_user=<user name>' in hublot-config.toml");
-            app_config.pricer_password.expect("Pricer password is empty in the config file, please add 'pricer_password=<password>' in hublot-config.toml");
-            println!(
-                "{} {}Starting the application loop...",
-                style("[4/4]").bold().dim(),
-                ROCKET
+            if let Err(e) = services::backend::validate(&app_config) {
+                error!("The configuration is not complete: {}", e);
+                eprintln!("The configuration is not complete: {e}");
+                return;
+            }
+            report_step(
+                headless,
+                "[4/4]",
+                &ROCKET,
+                "Starting the application loop...".to_string(),
             );
         }
         loop {
-            let app_config = app_config.clone();
-            let poller = tokio::task::spawn(async move { polling_worker(app_config).await }).await;
+            if *shutdown.borrow() {
+                break;
+            }
+            let app_config = config_receiver.borrow_and_update().clone();
+            let reload = config_receiver.clone();
+            let worker_shutdown = shutdown.clone();
+            let poller = tokio::task::spawn(async move {
+                polling_worker(app_config, reload, worker_shutdown).await
+            })
+            .await;
             match poller {
                 Ok(output) if output.is_err() => {
                     error!("The poller have crashed from an unrecoverable error. Restarting it in a few seconds");
                     error!("Cause of the crash: {}", output.unwrap_err());
                 }
+                Ok(_output) if *shutdown.borrow() => break,
+                Ok(_output) if config_receiver.has_changed().unwrap_or(false) => {
+                    info!("Restarting the poller with the reloaded configuration");
+                    continue;
+                }
                 Ok(_output) => {
                     error!("The poller have stopped with a successfull response. It is not an intended behavior please check the logs above.");
                     break;
@@ -259,7 +388,43 @@ async fn main() -> Result<(), MainError> {
             sleep(Duration::from_millis(2000)).await;
         }
     });
-    let (poll_result,) = tokio::join!(spawn_poll);
+    let mut drained = true;
+    let poll_result = tokio::select! {
+        poll_result = &mut spawn_poll => poll_result,
+        signal = stop_requested(&mut dashboard_task) => {
+            let timeout = Duration::from_secs(live_config.borrow().shutdown_timeout.unwrap_or(60));
+            report_step(
+                headless,
+                "[stop]",
+                &CONFIG,
+                format!("Received {signal}, waiting up to {}s for the in-flight ESL updates...", timeout.as_secs()),
+            );
+            shutdown_sender.send_replace(true);
+            match tokio::time::timeout(timeout, &mut spawn_poll).await {
+                Ok(poll_result) => poll_result,
+                Err(_) => {
+                    error!("The in-flight ESL updates did not finish in time, they will be replayed from the outbox on the next run");
+                    spawn_poll.abort();
+                    drained = false;
+                    Ok(())
+                }
+            }
+        }
+    };
+    // the dashboard restores the terminal once the shutdown is set
+    shutdown_sender.send_replace(true);
+    if let Some(task) = dashboard_task.filter(|task| !task.is_finished()) {
+        if let Ok(Err(e)) = task.await {
+            error!("The dashboard has failed: {}", e);
+        }
+    }
     poll_result.expect("Polling stopped for some unknown reason");
-    Ok(())
+
+    if !log_shipper.flush(PARSE_FLUSH_TIMEOUT).await {
+        error!("Some logs could not be sent to Parse nor spooled before exiting");
+        drained = false;
+    }
+    info!("The broker has stopped");
+    log::logger().flush();
+    Ok(if drained { 0 } else { 1 })
 }
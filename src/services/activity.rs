@@ -0,0 +1,160 @@
+use std::{collections::VecDeque, sync::Mutex};
+
+use chrono::Local;
+use lazy_static::lazy_static;
+use serde::Serialize;
+
+use super::esl_service::HublotPrintRequest;
+
+/// Number of print requests kept for the dashboard, the oldest ones are forgotten
+const MAX_ACTIVITIES: usize = 200;
+
+lazy_static! {
+    /// The recent print requests and the progress of their update, shown by the dashboard and the admin api
+    pub static ref ACTIVITY: Activity = Activity::default();
+}
+
+/// A stage of the update of a print request, see `backend::on_poll`
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Stage {
+    Map,
+    Update,
+    Confirm,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum StageStatus {
+    /// The stage has not been reached yet
+    Waiting,
+    Succeeded,
+    Failed,
+}
+
+/// Where a print request stands in the polling worker
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ActivityState {
+    InProgress,
+    Succeeded,
+    /// The esl server rejected the update, it will not be retried
+    Rejected,
+    /// The update failed and waits for the next cycle
+    Retrying,
+    /// The esl server is paused, the update waits without losing an attempt
+    Postponed,
+    /// The update failed `max_esl_attempts` times, it is given up
+    Failed,
+}
+
+impl ActivityState {
+    /// The print request reached a final outcome without being displayed
+    pub fn is_failure(&self) -> bool {
+        matches!(self, ActivityState::Rejected | ActivityState::Failed)
+    }
+}
+
+/// The progress of the update of a print request
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PrintActivity {
+    pub barcode: String,
+    pub rid: Option<String>,
+    /// RFC 3339 date of the last attempt
+    pub at: String,
+    pub map: StageStatus,
+    pub update: StageStatus,
+    pub confirm: StageStatus,
+    pub state: ActivityState,
+    pub attempts: u32,
+    /// The error of the last attempt, as reported by the esl server
+    pub error: Option<String>,
+    /// Kept to retry the print request from the dashboard
+    #[serde(skip)]
+    pub request: HublotPrintRequest,
+}
+
+#[derive(Default)]
+pub struct Activity {
+    /// The most recent print request first
+    activities: Mutex<VecDeque<PrintActivity>>,
+}
+
+impl Activity {
+    /// Records a new attempt to update `request`, it moves to the top of the list
+    pub fn start(&self, request: &HublotPrintRequest) {
+        let mut activities = self.activities.lock().unwrap();
+        let attempts = match activities
+            .iter()
+            .position(|activity| activity.barcode == request.id())
+        {
+            Some(i) => activities.remove(i).map_or(0, |activity| activity.attempts),
+            None => 0,
+        };
+        activities.push_front(PrintActivity {
+            barcode: request.id().to_string(),
+            rid: request.rid().map(str::to_string),
+            at: Local::now().to_rfc3339(),
+            map: StageStatus::Waiting,
+            update: StageStatus::Waiting,
+            confirm: StageStatus::Waiting,
+            state: ActivityState::InProgress,
+            attempts,
+            error: None,
+            request: request.clone(),
+        });
+        activities.truncate(MAX_ACTIVITIES);
+    }
+
+    /// Records the result of a stage of the update of esl `barcode`
+    pub fn stage(&self, barcode: &str, stage: Stage, result: Result<(), String>) {
+        self.with(barcode, |activity| {
+            let status = if result.is_ok() {
+                StageStatus::Succeeded
+            } else {
+                StageStatus::Failed
+            };
+            match stage {
+                Stage::Map => activity.map = status,
+                Stage::Update => activity.update = status,
+                Stage::Confirm => activity.confirm = status,
+            }
+            if let Err(error) = result {
+                activity.error = Some(error);
+            }
+        });
+    }
+
+    /// Records where the update of esl `barcode` stands once the polling worker is done with it
+    pub fn finish(
+        &self,
+        barcode: &str,
+        state: ActivityState,
+        attempts: u32,
+        error: Option<String>,
+    ) {
+        self.with(barcode, |activity| {
+            activity.state = state;
+            activity.attempts = attempts;
+            if error.is_some() {
+                activity.error = error;
+            }
+        });
+    }
+
+    /// The recent print requests, the most recent first
+    pub fn recent(&self) -> Vec<PrintActivity> {
+        self.activities.lock().unwrap().iter().cloned().collect()
+    }
+
+    fn with(&self, barcode: &str, update: impl FnOnce(&mut PrintActivity)) {
+        let mut activities = self.activities.lock().unwrap();
+        if let Some(activity) = activities
+            .iter_mut()
+            .find(|activity| activity.barcode == barcode)
+        {
+            update(activity);
+        }
+    }
+}
@@ -0,0 +1,355 @@
+use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+
+use hyper::{
+    header::{AUTHORIZATION, CONTENT_TYPE},
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use log::{debug, info, warn};
+use serde_json::json;
+use tokio::sync::watch;
+
+use super::{
+    activity::{ActivityState, ACTIVITY},
+    backend::EslBackend,
+    control::CONTROL,
+    esl_service::{HublotPrintRequest, Origin},
+    pricer_service::PricerBackend,
+};
+use crate::settings::Settings;
+
+custom_error! {
+    /// An error that can occur while running the admin api.
+    ///
+    /// This error can be seamlessly converted from a `hyper::Error` via a `From` implementation.
+    pub AdminError
+        Hyper{source: hyper::Error} = "The admin api has failed: {source}",
+        MissingToken = "The admin api requires an admin_token in hublot-config.toml"
+}
+
+struct AdminState {
+    /// The live configuration, the Pricer calls follow its reloads
+    config: watch::Receiver<Settings>,
+    token: String,
+}
+
+/// Serves the admin api on `127.0.0.1:port` until the process stops.
+///
+/// Every request must carry the `admin_token` of `config` as a bearer token.
+pub async fn serve(port: u16, config: watch::Receiver<Settings>) -> Result<(), AdminError> {
+    let token = config
+        .borrow()
+        .admin_token
+        .clone()
+        .filter(|token| !token.is_empty())
+        .ok_or(AdminError::MissingToken)?;
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    let state = Arc::new(AdminState { config, token });
+    let make_service = make_service_fn(move |_conn| {
+        let state = state.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |request| route(request, state.clone()))) }
+    });
+    let server = Server::try_bind(&addr)?.serve(make_service);
+    info!("Admin api listening on http://{}", addr);
+    Ok(server.await?)
+}
+
+async fn route(
+    request: Request<Body>,
+    state: Arc<AdminState>,
+) -> Result<Response<Body>, Infallible> {
+    debug!("Admin request: {} {}", request.method(), request.uri());
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let response = if !authorized(&request, &state.token) {
+        warn!(
+            "Rejected an admin request without a valid token: {} {}",
+            method, path
+        );
+        json_response(
+            StatusCode::UNAUTHORIZED,
+            json!({ "error": "a valid bearer token is required" }),
+        )
+    } else {
+        match (method, segments.as_slice()) {
+            (Method::POST, ["esls"]) => push(request).await,
+            (Method::GET, ["labels", barcode]) => label(&state, barcode).await,
+            (Method::GET, ["requests", request_id]) => items_result(&state, request_id).await,
+            (Method::POST, ["polling", "pause"]) => {
+                CONTROL.pause();
+                info!("Polling paused from the admin api");
+                json_response(StatusCode::OK, json!({ "paused": true }))
+            }
+            (Method::POST, ["polling", "resume"]) => {
+                CONTROL.resume();
+                info!("Polling resumed from the admin api");
+                json_response(StatusCode::OK, json!({ "paused": false }))
+            }
+            (Method::GET, ["updates"]) => updates(),
+            _ => Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::empty()),
+        }
+    };
+    Ok(response.unwrap_or_else(|_| {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+        response
+    }))
+}
+
+/// Checks the bearer token of `request`, compared in constant time so it cannot be guessed from the response time
+fn authorized(request: &Request<Body>, token: &str) -> bool {
+    let provided = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.strip_prefix("Bearer "));
+    match provided {
+        Some(provided) => {
+            provided.len() == token.len()
+                && provided
+                    .bytes()
+                    .zip(token.bytes())
+                    .fold(0, |diff, (a, b)| diff | (a ^ b))
+                    == 0
+        }
+        None => false,
+    }
+}
+
+fn json_response(
+    status: StatusCode,
+    body: serde_json::Value,
+) -> hyper::http::Result<Response<Body>> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+}
+
+/// Queues the print request in the body for the next polling cycle.
+///
+/// The body is a `GenericEsl`, the `displayPosition` and `allPositions` of the targeted items are
+/// read from the query or the body. The print request is neither journaled in the outbox nor
+/// acknowledged to the Hublot server.
+async fn push(request: Request<Body>) -> hyper::http::Result<Response<Body>> {
+    let query = request.uri().query().map(str::to_string);
+    let print_request = match hyper::body::to_bytes(request.into_body()).await {
+        Ok(body) => serde_json::from_slice::<HublotPrintRequest>(&body).map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+    .and_then(|print_request| with_target(print_request, query.as_deref()));
+    match print_request {
+        Ok(mut print_request) => {
+            print_request.origin = Origin::Admin;
+            info!(
+                barcode = print_request.id(), rid = print_request.rid();
+                "Queued the print request of esl {} from the admin api",
+                print_request.id()
+            );
+            let barcode = print_request.id().to_string();
+            CONTROL.inject(print_request);
+            json_response(
+                StatusCode::ACCEPTED,
+                json!({ "barcode": barcode, "paused": CONTROL.is_paused() }),
+            )
+        }
+        Err(e) => json_response(
+            StatusCode::BAD_REQUEST,
+            json!({ "error": format!("Invalid print request: {e}") }),
+        ),
+    }
+}
+
+/// Sets the items targeted by `request` from the `displayPosition` and `allPositions` of `query`
+fn with_target(
+    mut request: HublotPrintRequest,
+    query: Option<&str>,
+) -> Result<HublotPrintRequest, String> {
+    let pairs = query
+        .unwrap_or_default()
+        .split('&')
+        .filter_map(|pair| pair.split_once('='));
+    for (key, value) in pairs {
+        match key {
+            "displayPosition" => {
+                request.display_position = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("displayPosition '{value}' is not a number"))?,
+                )
+            }
+            "allPositions" => {
+                request.all_positions = value
+                    .parse()
+                    .map_err(|_| format!("allPositions '{value}' is not true or false"))?
+            }
+            _ => {}
+        }
+    }
+    Ok(request)
+}
+
+/// The Pricer backend of the live configuration, or the status and error to answer with.
+///
+/// `from_settings` hands out the client, the circuit breaker and the label cache shared with the
+/// polling worker, so the admin calls count against the same breaker.
+fn pricer_backend(state: &AdminState) -> Result<PricerBackend, (StatusCode, String)> {
+    let config = state.config.borrow().clone();
+    let vendor = config.esl_vendor.as_deref().unwrap_or(PricerBackend::NAME);
+    if vendor != PricerBackend::NAME {
+        return Err((
+            StatusCode::NOT_IMPLEMENTED,
+            format!("only available with the Pricer backend, esl_vendor is '{vendor}'"),
+        ));
+    }
+    PricerBackend::from_settings(&config)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+/// The Pricer items linked to the label `barcode`
+async fn label(state: &AdminState, barcode: &str) -> hyper::http::Result<Response<Body>> {
+    let backend = match pricer_backend(state) {
+        Ok(backend) => backend,
+        Err((status, error)) => return json_response(status, json!({ "error": error })),
+    };
+    match backend.label(barcode).await {
+        Ok(label) => json_response(StatusCode::OK, json!(label)),
+        Err(e) => json_response(StatusCode::BAD_GATEWAY, json!({ "error": e.to_string() })),
+    }
+}
+
+/// The result of the Pricer update request `request_id`
+async fn items_result(state: &AdminState, request_id: &str) -> hyper::http::Result<Response<Body>> {
+    let Ok(request_id) = request_id.parse::<i32>() else {
+        return json_response(
+            StatusCode::BAD_REQUEST,
+            json!({ "error": format!("'{request_id}' is not a Pricer requestId") }),
+        );
+    };
+    let backend = match pricer_backend(state) {
+        Ok(backend) => backend,
+        Err((status, error)) => return json_response(status, json!({ "error": error })),
+    };
+    match backend.items_result(request_id).await {
+        Ok(Some(result)) => json_response(StatusCode::OK, json!(result)),
+        Ok(None) => json_response(
+            StatusCode::NOT_FOUND,
+            json!({ "error": format!("request {request_id} is unknown to the Pricer server") }),
+        ),
+        Err(e) => json_response(StatusCode::BAD_GATEWAY, json!({ "error": e.to_string() })),
+    }
+}
+
+/// The print requests waiting for an update and the ones that failed, the most recent first
+fn updates() -> hyper::http::Result<Response<Body>> {
+    let activities = ACTIVITY.recent();
+    let injected: Vec<String> = CONTROL
+        .injected()
+        .iter()
+        .map(|request| request.id().to_string())
+        .collect();
+    let queued: Vec<_> = activities
+        .iter()
+        .filter(|activity| {
+            matches!(
+                activity.state,
+                ActivityState::InProgress | ActivityState::Retrying | ActivityState::Postponed
+            )
+        })
+        .collect();
+    let failed: Vec<_> = activities
+        .iter()
+        .filter(|activity| activity.state.is_failure())
+        .collect();
+    json_response(
+        StatusCode::OK,
+        json!({
+            "paused": CONTROL.is_paused(),
+            "injected": injected,
+            "queued": queued,
+            "failed": failed,
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use config::{Config, File, FileFormat};
+    use serde_json::json;
+
+    use super::*;
+
+    fn request(authorization: Option<&str>) -> Request<Body> {
+        let mut request = Request::builder().uri("/updates");
+        if let Some(authorization) = authorization {
+            request = request.header(AUTHORIZATION, authorization);
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    fn print_request() -> HublotPrintRequest {
+        serde_json::from_value(json!({
+            "id": "0001",
+            "nom": "Bar",
+            "nom_scientifique": "Dicentrarchus labrax",
+            "plu": "1234",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn authorizes_the_admin_token() {
+        assert!(authorized(&request(Some("Bearer secret")), "secret"));
+    }
+
+    #[test]
+    fn rejects_a_missing_or_wrong_token() {
+        assert!(!authorized(&request(None), "secret"));
+        assert!(!authorized(&request(Some("Bearer secreT")), "secret"));
+        assert!(!authorized(&request(Some("Bearer secret2")), "secret"));
+        assert!(!authorized(&request(Some("Bearer ")), "secret"));
+        assert!(!authorized(&request(Some("secret")), "secret"));
+        assert!(!authorized(&request(Some("Basic secret")), "secret"));
+    }
+
+    #[test]
+    fn the_pricer_calls_need_the_pricer_backend() {
+        let config: Settings = Config::builder()
+            .add_source(File::from_str(
+                r#"esl_vendor = "hanshow""#,
+                FileFormat::Toml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+        let state = AdminState {
+            config: watch::channel(config).1,
+            token: "secret".to_string(),
+        };
+        let Err((status, _)) = pricer_backend(&state) else {
+            panic!("the Pricer backend was built for the hanshow vendor");
+        };
+        assert_eq!(status, StatusCode::NOT_IMPLEMENTED);
+    }
+
+    #[test]
+    fn reads_the_target_from_the_query() {
+        let request = with_target(print_request(), Some("displayPosition=2")).unwrap();
+        assert_eq!(request.display_position, Some(2));
+        assert!(!request.all_positions);
+        let request = with_target(print_request(), Some("allPositions=true")).unwrap();
+        assert!(request.all_positions);
+        let request = with_target(print_request(), None).unwrap();
+        assert_eq!(request.display_position, None);
+    }
+
+    #[test]
+    fn rejects_an_invalid_target() {
+        assert!(with_target(print_request(), Some("displayPosition=first")).is_err());
+        assert!(with_target(print_request(), Some("allPositions=1")).is_err());
+    }
+}
@@ -0,0 +1,433 @@
+use std::fmt::Debug;
+
+use super::{
+    activity::{Stage, ACTIVITY},
+    esl_service::HublotPrintRequest,
+    hanshow_service::{HanshowBackend, HanshowError},
+    poll::{poll, PollingError},
+    pricer_service::{PricerBackend, PricerError},
+    ClientError,
+};
+use crate::settings::Settings;
+use crate::utils::progress::status;
+use indicatif::ProgressBar;
+use log::{debug, Level};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+custom_error! {
+    /// An error that can occur while calling an ESL backend.
+    ///
+    /// This error can be seamlessly converted from every vendor error via a `From`
+    /// implementation.
+    pub BackendError
+        PricerError{source: PricerError} = "An issue occured calling the PricerService: {source}",
+        HanshowError{source: HanshowError} = "An issue occured calling the HanshowService: {source}",
+        ClientError{source: ClientError} = "Unable to build the esl server client: {source}",
+        ChunkFailed{reason: String} = "The update of this chunk of items has failed: {reason}",
+        UnknownBackend{name: String} = "Unknown esl_vendor '{name}' in hublot-config.toml, expected 'pricer' or 'hanshow'",
+        MissingKey{backend: String, key: String} = "The {backend} backend is missing a configuration key, please add '{key}=<value>' in hublot-config.toml",
+        Paused{reason: String} = "The requests to the esl server are paused: {reason}"
+}
+
+impl BackendError {
+    /// Fatal errors cannot be solved by retrying a single ESL, the polling worker has to restart
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            BackendError::PricerError {
+                source: PricerError::Reqwest { source },
+            }
+            | BackendError::HanshowError {
+                source: HanshowError::Reqwest { source },
+            } => source.is_builder(),
+            BackendError::PricerError {
+                source: PricerError::Io { .. },
+            }
+            | BackendError::HanshowError {
+                source: HanshowError::Io { .. },
+            } => true,
+            BackendError::ClientError { .. }
+            | BackendError::UnknownBackend { .. }
+            | BackendError::MissingKey { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// The esl server is paused by a circuit breaker, retrying should not count as an attempt
+    pub fn is_paused(&self) -> bool {
+        matches!(
+            self,
+            BackendError::PricerError {
+                source: PricerError::CircuitOpen { .. },
+            } | BackendError::Paused { .. }
+        )
+    }
+}
+
+/// The final outcome of an update request
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum UpdateOutcome {
+    Succeeded,
+    /// The vendor rejected the update, the reasons are listed in `UpdateReport::errors`
+    Failed,
+    /// The vendor did not report a final status before the deadline
+    TimedOut,
+}
+
+/// The vendor agnostic result of an update request
+#[derive(Debug, Clone)]
+pub struct UpdateReport {
+    pub request_id: String,
+    /// The last status reported by the vendor
+    pub status: String,
+    pub errors: Vec<String>,
+    pub outcome: UpdateOutcome,
+}
+
+impl UpdateReport {
+    /// Merges the reports of the items updated for a single print request.
+    ///
+    /// The merged outcome is the worst one: a failure, then a timeout, then a success.
+    pub fn merge(reports: Vec<UpdateReport>) -> Option<UpdateReport> {
+        let outcome = reports
+            .iter()
+            .map(|report| report.outcome)
+            .max_by_key(|outcome| match outcome {
+                UpdateOutcome::Succeeded => 0,
+                UpdateOutcome::TimedOut => 1,
+                UpdateOutcome::Failed => 2,
+            })?;
+        let mut request_ids: Vec<String> = reports
+            .iter()
+            .map(|report| report.request_id.clone())
+            .collect();
+        request_ids.dedup();
+        let mut statuses: Vec<String> =
+            reports.iter().map(|report| report.status.clone()).collect();
+        statuses.dedup();
+        Some(UpdateReport {
+            request_id: request_ids.join(", "),
+            status: statuses.join(", "),
+            errors: reports
+                .into_iter()
+                .flat_map(|report| report.errors)
+                .collect(),
+            outcome,
+        })
+    }
+}
+
+/// An ESL vendor api able to display the print requests fetched from the Hublot server.
+pub trait EslBackend {
+    /// The name used to select this backend with the `esl_vendor` configuration key
+    const NAME: &'static str;
+    /// The vendor representation of an item displayed on a label
+    type Esl: Clone + Debug;
+    /// The vendor acknowledgement of an update request
+    type Ticket: Debug;
+
+    /// Builds the backend from the app configuration
+    fn from_settings(config: &Settings) -> Result<Self, BackendError>
+    where
+        Self: Sized;
+
+    /// The configuration keys required by `from_settings` that are missing from `config`
+    fn missing_keys(config: &Settings) -> Vec<&'static str>
+    where
+        Self: Sized;
+
+    /// Prepares the backend before the first polling cycle
+    async fn warm_up(&self) -> Result<(), BackendError> {
+        Ok(())
+    }
+
+    /// Maps the esl barcode to the vendor items targeted by the print request.
+    ///
+    /// The backends record their calls to the esl server in `METRICS`, a mapping served from a cache is not a call.
+    async fn map(&self, request: &HublotPrintRequest) -> Result<Vec<Self::Esl>, BackendError>;
+
+    /// Requests the vendor to update a chunk of mapped items with a single request
+    async fn update(&self, esls: &[Self::Esl]) -> Result<Self::Ticket, BackendError>;
+
+    /// Waits for the final result of an update request, with one report per item of `esls`, in the same order
+    async fn confirm(
+        &self,
+        ticket: Self::Ticket,
+        esls: &[Self::Esl],
+    ) -> Result<Vec<UpdateReport>, BackendError>;
+
+    /// Returns true when the vendor api is reachable
+    async fn health(&self) -> Result<bool, BackendError>;
+}
+
+/// Pushes a chunk of print requests through the map, update and confirm steps of `backend`.
+///
+/// Returns one outcome per print request, in the same order, so that a failing ESL does not
+/// prevent the others from being updated. Only fatal errors are returned as an `Err`.
+pub async fn on_poll<B: EslBackend>(
+    backend: &B,
+    requests: &[HublotPrintRequest],
+    pb: &ProgressBar,
+) -> Result<Vec<Result<UpdateReport, BackendError>>, BackendError> {
+    let mut outcomes: Vec<Option<Result<UpdateReport, BackendError>>> =
+        requests.iter().map(|_| None).collect();
+    //first: We need to map each esl barcode to the vendor items it displays
+    let mut mapped_esls = Vec::new();
+    // the index of the print request of every mapped item
+    let mut owners = Vec::new();
+    for (i, request) in requests.iter().enumerate() {
+        pb.inc(1);
+        status!(
+            pb,
+            Level::Debug,
+            "[1/3] Getting items for esl id {}",
+            request.id()
+        );
+        let mapped = backend.map(request).await;
+        record_stage(requests, &[i], Stage::Map, &mapped);
+        match mapped {
+            Ok(items) => {
+                debug!(barcode = request.id(); "Got mapped ESL: {:?}", items);
+                owners.extend(items.iter().map(|_| i));
+                mapped_esls.extend(items);
+            }
+            Err(e) if e.is_fatal() => return Err(e),
+            Err(e) => outcomes[i] = Some(Err(e)),
+        }
+    }
+
+    if !mapped_esls.is_empty() {
+        pb.inc(1);
+        status!(
+            pb,
+            Level::Debug,
+            "[2/3] Updating {} items with {}",
+            mapped_esls.len(),
+            B::NAME
+        );
+        // then we can request the vendor to update the whole chunk at once
+        let updated = backend.update(&mapped_esls).await;
+        record_stage(requests, &owners, Stage::Update, &updated);
+        let reports = match updated {
+            Ok(ticket) => {
+                debug!("Got request status: {:?}", ticket);
+                pb.inc(1);
+                status!(
+                    pb,
+                    Level::Debug,
+                    "[3/3] Checking update status for {:?}",
+                    ticket
+                );
+                let confirmed = backend.confirm(ticket, &mapped_esls).await;
+                if confirmed.is_err() {
+                    record_stage(requests, &owners, Stage::Confirm, &confirmed);
+                }
+                confirmed
+            }
+            Err(e) => Err(e),
+        };
+        match reports {
+            Ok(reports) => {
+                debug!("Got update_status {:?}", reports);
+                let mut item_reports: Vec<Vec<UpdateReport>> =
+                    requests.iter().map(|_| Vec::new()).collect();
+                for (i, report) in owners.into_iter().zip(reports) {
+                    item_reports[i].push(report);
+                }
+                for (i, reports) in item_reports.into_iter().enumerate() {
+                    if let Some(report) = UpdateReport::merge(reports) {
+                        let confirmed = match report.outcome {
+                            UpdateOutcome::Succeeded => Ok(()),
+                            _ if report.errors.is_empty() => Err(report.status.clone()),
+                            _ => Err(report.errors.join(", ")),
+                        };
+                        ACTIVITY.stage(requests[i].id(), Stage::Confirm, confirmed);
+                        outcomes[i] = Some(Ok(report));
+                    }
+                }
+            }
+            Err(e) if e.is_fatal() => return Err(e),
+            Err(e) => {
+                let paused = e.is_paused();
+                let reason = e.to_string();
+                for i in owners {
+                    outcomes[i] = Some(Err(if paused {
+                        BackendError::Paused {
+                            reason: reason.clone(),
+                        }
+                    } else {
+                        BackendError::ChunkFailed {
+                            reason: reason.clone(),
+                        }
+                    }));
+                }
+            }
+        }
+    }
+
+    Ok(outcomes
+        .into_iter()
+        .map(|outcome| {
+            outcome.unwrap_or_else(|| {
+                Err(BackendError::ChunkFailed {
+                    reason: "the esl server did not report this item".to_string(),
+                })
+            })
+        })
+        .collect())
+}
+
+/// Records the result of a stage of `on_poll` for the print requests at the `indexes` of `requests`
+fn record_stage<T>(
+    requests: &[HublotPrintRequest],
+    indexes: &[usize],
+    stage: Stage,
+    result: &Result<T, BackendError>,
+) {
+    let result = result.as_ref().map(|_| ()).map_err(ToString::to_string);
+    for &i in indexes {
+        ACTIVITY.stage(requests[i].id(), stage, result.clone());
+    }
+}
+
+/// Binds the type `$backend` to the backend named by the `esl_vendor` key of `$config` and evaluates `$body`.
+///
+/// Supporting a new vendor only requires an `EslBackend` implementation registered here.
+macro_rules! with_backend_type {
+    ($config:expr, |$backend:ident| $body:expr) => {
+        match $config.esl_vendor.as_deref().unwrap_or(PricerBackend::NAME) {
+            PricerBackend::NAME => {
+                type $backend = PricerBackend;
+                $body
+            }
+            HanshowBackend::NAME => {
+                type $backend = HanshowBackend;
+                $body
+            }
+            name => Err(BackendError::UnknownBackend {
+                name: name.to_string(),
+            }
+            .into()),
+        }
+    };
+}
+
+/// Binds `$backend` to the backend named by the `esl_vendor` key of `$config` and evaluates `$body`
+macro_rules! with_backend {
+    ($config:expr, |$backend:ident| $body:expr) => {
+        with_backend_type!($config, |Backend| {
+            let $backend = Backend::from_settings($config)?;
+            $body
+        })
+    };
+}
+
+/// The configuration keys required by the backend named by `esl_vendor` that are missing from `config`
+pub fn missing_keys(config: &Settings) -> Result<Vec<&'static str>, BackendError> {
+    with_backend_type!(config, |Backend| Ok(Backend::missing_keys(config)))
+}
+
+/// Makes sure that the backend named by `esl_vendor` can be built from `config`
+pub fn validate(config: &Settings) -> Result<(), BackendError> {
+    with_backend!(config, |_backend| Ok(()))
+}
+
+/// Returns true when the api of the backend named by `esl_vendor` is reachable
+pub async fn health(config: &Settings) -> Result<bool, BackendError> {
+    with_backend!(config, |backend| backend.health().await)
+}
+
+/// Pushes a single print request through the backend named by `esl_vendor`
+pub async fn push(
+    config: &Settings,
+    request: &HublotPrintRequest,
+) -> Result<Result<UpdateReport, BackendError>, BackendError> {
+    with_backend!(config, |backend| {
+        let mut outcomes = on_poll(
+            &backend,
+            std::slice::from_ref(request),
+            &ProgressBar::hidden(),
+        )
+        .await?;
+        Ok(outcomes.remove(0))
+    })
+}
+
+/// Starts the polling worker with the backend named by `esl_vendor`
+pub async fn poll_with_backend(
+    config: &Settings,
+    client: Client,
+    reload: &watch::Receiver<Settings>,
+    shutdown: &watch::Receiver<bool>,
+) -> Result<(), PollingError> {
+    with_backend!(config, |backend| poll(
+        backend, config, client, reload, shutdown
+    )
+    .await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(request_id: &str, status: &str, outcome: UpdateOutcome) -> UpdateReport {
+        UpdateReport {
+            request_id: request_id.to_string(),
+            status: status.to_string(),
+            errors: match outcome {
+                UpdateOutcome::Failed => vec![format!("{request_id} was rejected")],
+                _ => Vec::new(),
+            },
+            outcome,
+        }
+    }
+
+    #[test]
+    fn merges_nothing() {
+        assert!(UpdateReport::merge(Vec::new()).is_none());
+    }
+
+    #[test]
+    fn keeps_a_single_report() {
+        let merged =
+            UpdateReport::merge(vec![report("42", "SUCCESSFUL", UpdateOutcome::Succeeded)])
+                .unwrap();
+        assert_eq!(merged.request_id, "42");
+        assert_eq!(merged.status, "SUCCESSFUL");
+        assert_eq!(merged.outcome, UpdateOutcome::Succeeded);
+        assert!(merged.errors.is_empty());
+    }
+
+    #[test]
+    fn the_worst_outcome_wins() {
+        let merged = UpdateReport::merge(vec![
+            report("42", "SUCCESSFUL", UpdateOutcome::Succeeded),
+            report("43", "PENDING", UpdateOutcome::TimedOut),
+        ])
+        .unwrap();
+        assert_eq!(merged.outcome, UpdateOutcome::TimedOut);
+        let merged = UpdateReport::merge(vec![
+            report("42", "PENDING", UpdateOutcome::TimedOut),
+            report("43", "FAILED", UpdateOutcome::Failed),
+            report("44", "SUCCESSFUL", UpdateOutcome::Succeeded),
+        ])
+        .unwrap();
+        assert_eq!(merged.outcome, UpdateOutcome::Failed);
+        assert_eq!(merged.request_id, "42, 43, 44");
+        assert_eq!(merged.status, "PENDING, FAILED, SUCCESSFUL");
+        assert_eq!(merged.errors, vec!["43 was rejected"]);
+    }
+
+    #[test]
+    fn lists_a_shared_request_once() {
+        let merged = UpdateReport::merge(vec![
+            report("42", "SUCCESSFUL", UpdateOutcome::Succeeded),
+            report("42", "SUCCESSFUL", UpdateOutcome::Succeeded),
+        ])
+        .unwrap();
+        assert_eq!(merged.request_id, "42");
+        assert_eq!(merged.status, "SUCCESSFUL");
+    }
+}
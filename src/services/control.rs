@@ -0,0 +1,62 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex,
+};
+
+use lazy_static::lazy_static;
+
+use super::esl_service::HublotPrintRequest;
+
+lazy_static! {
+    /// The requests sent to the polling worker from outside of the polling loop, by the dashboard or the admin api
+    pub static ref CONTROL: Control = Control::default();
+}
+
+#[derive(Default)]
+pub struct Control {
+    poll_requested: AtomicBool,
+    paused: AtomicBool,
+    /// The print requests to update on the next cycle, along with the fetched ones
+    injected: Mutex<Vec<HublotPrintRequest>>,
+}
+
+impl Control {
+    /// Ends the wait of the polling worker, the Hublot server is polled right away
+    pub fn poll_now(&self) {
+        self.poll_requested.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns true once after `poll_now` has been called
+    pub fn take_poll_request(&self) -> bool {
+        self.poll_requested.swap(false, Ordering::Relaxed)
+    }
+
+    /// Queues `request` for the next cycle with fresh attempts, e.g. to retry a failed ESL
+    pub fn inject(&self, request: HublotPrintRequest) {
+        self.injected.lock().unwrap().push(request);
+        self.poll_now();
+    }
+
+    pub fn take_injected(&self) -> Vec<HublotPrintRequest> {
+        std::mem::take(&mut *self.injected.lock().unwrap())
+    }
+
+    /// The print requests queued by `inject`, not yet picked up by the polling worker
+    pub fn injected(&self) -> Vec<HublotPrintRequest> {
+        self.injected.lock().unwrap().clone()
+    }
+
+    /// Stops polling the Hublot server and updating the ESLs, the pending requests wait for `resume`
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::Relaxed);
+        self.poll_now();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+}
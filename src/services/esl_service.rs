@@ -1,68 +1,109 @@
 use std::{io, str::Utf8Error};
 
-use crate::utils::unicode_string;
+use super::backend::{BackendError, UpdateOutcome, UpdateReport};
 use esl_utils::generic_esl::GenericEsl;
 use log::{debug, trace};
 use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
 
-/// The representation of the state of an Electronic Shelf Label
+/// A print request fetched from the Hublot server
 #[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct Esl {
-    pub template: String,
-    pub sid: String,
-    pub back_url: String,
-    pub epd_type: String,
-    pub block_num: i64,
-    pub page: i64,
-    pub esl_id: String,
-    pub encoding: String,
-    #[serde(rename = "prixUnitaire")]
-    pub prix_unitaire: f64,
-    pub direction: String,
-    #[serde(with = "unicode_string")]
-    pub name: String,
-    #[serde(rename = "itemScientName", with = "unicode_string")]
-    pub item_scient_name: String,
-    #[serde(rename = "barcodeProduit")]
-    pub barcode_produit: String,
-    #[serde(rename = "itemName", with = "unicode_string")]
-    pub item_name: String,
-    #[serde(with = "unicode_string")]
-    pub mentiontraca: String,
-    #[serde(with = "unicode_string")]
-    pub zone: String,
-    #[serde(with = "unicode_string")]
-    pub souszone: String,
-    #[serde(with = "unicode_string")]
-    pub engin: String,
-    #[serde(with = "unicode_string")]
-    pub detailengin: String,
-    #[serde(rename = "NameLogo", with = "unicode_string")]
-    pub name_logo: String,
-    #[serde(with = "unicode_string")]
-    pub flagpromo: String,
-    #[serde(rename = "flagCongel", with = "unicode_string")]
-    pub flag_congel: String,
-    #[serde(rename = "TypeArt", with = "unicode_string")]
-    pub type_art: String,
-    #[serde(rename = "IDEtiquette", with = "unicode_string")]
-    pub idetiquette: String,
-    pub priority: i64,
+pub struct HublotPrintRequest {
+    #[serde(flatten)]
+    pub esl: GenericEsl,
+    /// The displayPosition of the item to update on a multi-item label, the first linked item when None
+    #[serde(rename = "displayPosition")]
+    pub display_position: Option<i32>,
+    /// Updates every item linked to the label, whatever their displayPosition
+    #[serde(default, rename = "allPositions")]
+    pub all_positions: bool,
+    /// Only the print requests of the Hublot server are journaled and acknowledged
+    #[serde(skip)]
+    pub origin: Origin,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct PrintRequestWrapper {
-    pub serial: String,
-    pub rid: String,
-    pub esl: Esl,
+/// Where a print request comes from
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Origin {
+    /// Fetched from the Hublot server, or replayed from the outbox
+    #[default]
+    Hublot,
+    /// Pushed through the admin api, the Hublot server does not know about it
+    Admin,
 }
 
+/// The items of a label targeted by a print request
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisplayTarget {
+    /// The first item linked to the label
+    First,
+    /// The item linked at this displayPosition
+    Position(i32),
+    /// Every item linked to the label
+    All,
+}
+
+impl HublotPrintRequest {
+    /// The barcode of the targeted label
+    pub fn id(&self) -> &str {
+        &self.esl.id
+    }
+
+    /// The objectId of the print request on the Hublot server
+    pub fn rid(&self) -> Option<&str> {
+        self.esl.object_id.as_deref()
+    }
+
+    /// The items of the label to update, `allPositions` wins over `displayPosition`
+    pub fn target(&self) -> DisplayTarget {
+        match (self.all_positions, self.display_position) {
+            (true, _) => DisplayTarget::All,
+            (false, Some(position)) => DisplayTarget::Position(position),
+            (false, None) => DisplayTarget::First,
+        }
+    }
+}
+
+/// The outcome of a print request, sent back to the Hublot server once the ESL backend answered
 #[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct PrintRequest {
-    pub state: i32,
-    pub rid: String,
-    pub esl: PrintRequestWrapper,
+pub struct PrintAcknowledgement {
+    #[serde(rename = "eslId")]
+    pub esl_id: String,
+    /// The objectId of the print request on the Hublot server
+    pub rid: Option<String>,
+    pub success: bool,
+    /// None when the print request failed before reaching the esl server
+    pub outcome: Option<UpdateOutcome>,
+    #[serde(rename = "requestId")]
+    pub request_id: Option<String>,
+    pub status: Option<String>,
+    pub errors: Vec<String>,
+}
+
+impl PrintAcknowledgement {
+    pub fn new(request: &HublotPrintRequest, outcome: &Result<UpdateReport, BackendError>) -> Self {
+        let esl = &request.esl;
+        match outcome {
+            Ok(report) => Self {
+                esl_id: esl.id.clone(),
+                rid: esl.object_id.clone(),
+                success: report.outcome == UpdateOutcome::Succeeded,
+                outcome: Some(report.outcome),
+                request_id: Some(report.request_id.clone()),
+                status: Some(report.status.clone()),
+                errors: report.errors.clone(),
+            },
+            Err(error) => Self {
+                esl_id: esl.id.clone(),
+                rid: esl.object_id.clone(),
+                success: false,
+                outcome: None,
+                request_id: None,
+                status: None,
+                errors: vec![error.to_string()],
+            },
+        }
+    }
 }
 
 custom_error! {
@@ -82,14 +123,14 @@ pub async fn get_print_requests(
     hublot_server_url: &str,
     client: &Client,
     client_serial: &str,
-) -> Result<Vec<GenericEsl>, EslServiceError> {
+) -> Result<Vec<HublotPrintRequest>, EslServiceError> {
     let url = format!("{hublot_server_url}/esl-api/poll/{client_serial}");
     trace!("Fetching esls status: {}", url);
     let response = client.get(url).send().await?;
 
     match response.status() {
         StatusCode::OK => {
-            let as_json: Vec<GenericEsl> = response.json().await?;
+            let as_json: Vec<HublotPrintRequest> = response.json().await?;
             trace!("Got esl status: {:?}", as_json);
             Ok(as_json)
         }
@@ -110,3 +151,28 @@ pub async fn status(hublot_server_url: &str, client: &Client) -> Result<bool, Es
     let response = client.get(url).send().await?;
     Ok(response.status() == StatusCode::OK)
 }
+
+/// Sends the outcome of a print request to the Hublot server
+pub async fn acknowledge(
+    hublot_server_url: &str,
+    client: &Client,
+    client_serial: &str,
+    acknowledgement: &PrintAcknowledgement,
+) -> Result<(), EslServiceError> {
+    let url = format!("{hublot_server_url}/esl-api/ack/{client_serial}");
+    trace!("Acknowledging print request: {} {:?}", url, acknowledgement);
+    let response = client.post(url).json(acknowledgement).send().await?;
+
+    match response.status() {
+        StatusCode::OK | StatusCode::CREATED | StatusCode::NO_CONTENT => Ok(()),
+        status => {
+            let content = response.bytes().await?;
+            let as_str = std::str::from_utf8(&content)?;
+            debug!("Esl service error: status={status}, payload={as_str}");
+            Err(EslServiceError::Custom {
+                status,
+                content: as_str.to_string(),
+            })
+        }
+    }
+}
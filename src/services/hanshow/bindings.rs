@@ -0,0 +1,81 @@
+use log::debug;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::services::{
+    esl_service::DisplayTarget,
+    hanshow_service::{HanshowError, HanshowEsl, HanshowResponse},
+};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct HanshowBinding {
+    sku: String,
+    position: i32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct HanshowEslInfo {
+    esl_id: String,
+    #[serde(default)]
+    bindings: Vec<HanshowBinding>,
+}
+
+/// Returns one esl per product bound to the esl_id and targeted by `target`
+pub async fn map_esl_to_sku(
+    esl: HanshowEsl,
+    target: DisplayTarget,
+    esl_server_url: &str,
+    hanshow_user: String,
+) -> Result<Vec<HanshowEsl>, HanshowError> {
+    let client = reqwest::Client::new();
+    let url = format!(
+        "{}/api2/{}/esls/{}",
+        esl_server_url, hanshow_user, esl.esl_id
+    );
+    let response = client.get(url).send().await?;
+
+    match response.status() {
+        StatusCode::OK => {
+            let body: HanshowResponse<HanshowEslInfo> = response.json().await?;
+            let success = body.is_success();
+            let bindings = match body.data {
+                Some(info) if success => info.bindings,
+                _ => {
+                    debug!("No bound products found: errno={}", body.errno);
+                    return Err(HanshowError::MissingBinding);
+                }
+            };
+            let selected: Vec<HanshowBinding> = match target {
+                // Default implem: use the first product bound to this esl
+                DisplayTarget::First => bindings.into_iter().take(1).collect(),
+                DisplayTarget::Position(position) => {
+                    let binding = bindings
+                        .into_iter()
+                        .find(|binding| binding.position == position)
+                        .ok_or_else(|| HanshowError::MissingPosition {
+                            esl_id: esl.esl_id.clone(),
+                            position,
+                        })?;
+                    vec![binding]
+                }
+                DisplayTarget::All => bindings,
+            };
+            if selected.is_empty() {
+                return Err(HanshowError::MissingBinding);
+            }
+            // every product needs its own sid to track its update
+            Ok(selected
+                .into_iter()
+                .map(|binding| HanshowEsl {
+                    sku: binding.sku,
+                    sid: uuid::Uuid::new_v4().to_string(),
+                    ..esl.clone()
+                })
+                .collect())
+        }
+        _ => {
+            debug!("No bound products found: {}", response.status());
+            Err(HanshowError::MissingBinding)
+        }
+    }
+}
@@ -0,0 +1,3 @@
+pub mod bindings;
+pub mod product;
+pub mod status;
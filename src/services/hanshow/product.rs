@@ -0,0 +1,64 @@
+use log::debug;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::services::hanshow_service::{HanshowError, HanshowEsl, HanshowResponse};
+
+#[derive(Serialize, Clone, Debug)]
+struct HanshowProducts<'a> {
+    data: Vec<&'a HanshowEsl>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HanshowAccepted {
+    pub sid: String,
+}
+
+fn skus(esls: &[HanshowEsl]) -> String {
+    let skus: Vec<&str> = esls.iter().map(|esl| esl.sku.as_str()).collect();
+    skus.join(", ")
+}
+
+/// Updates every product of `esls` with a single request, each product is tracked with its own sid
+pub async fn update_products(
+    esls: &[HanshowEsl],
+    esl_server_url: &str,
+    hanshow_user: String,
+) -> Result<Vec<HanshowAccepted>, HanshowError> {
+    let client = reqwest::Client::new();
+    let url = format!("{esl_server_url}/api2/{hanshow_user}/products");
+    let payload = HanshowProducts {
+        data: esls.iter().collect(),
+    };
+    let response = client.put(url).json(&payload).send().await?;
+    match response.status() {
+        StatusCode::OK | StatusCode::ACCEPTED => {
+            let body: HanshowResponse<serde_json::Value> = response.json().await?;
+            if body.is_accepted() {
+                debug!("Esl server accepted our update");
+                Ok(esls
+                    .iter()
+                    .map(|esl| HanshowAccepted {
+                        sid: esl.sid.clone(),
+                    })
+                    .collect())
+            } else {
+                debug!(
+                    "Esl server denied the update: errno={} errmsg={:?}",
+                    body.errno, body.errmsg
+                );
+                Err(HanshowError::UpdateFailed {
+                    sku: skus(esls),
+                    errno: body.errno,
+                })
+            }
+        }
+        _reqwest_error => {
+            debug!("Esl server denied the update: {}", response.status());
+            Err(HanshowError::UpdateFailed {
+                sku: skus(esls),
+                errno: -1,
+            })
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use std::time::Duration;
+
+use log::debug;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    services::hanshow_service::{HanshowError, HanshowResponse},
+    utils::backoff::Backoff,
+};
+
+use super::product::HanshowAccepted;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HanshowUpdateResult {
+    pub sid: String,
+    pub status: String,
+    #[serde(default)]
+    pub errors: Vec<String>,
+}
+
+/// The final outcome of an update request
+#[derive(Clone, Debug)]
+pub enum HanshowUpdateOutcome {
+    Completed(HanshowUpdateResult),
+    /// The deadline expired before the gateway reported a final status
+    TimedOut(Option<HanshowUpdateResult>),
+}
+
+impl HanshowUpdateResult {
+    /// Returns false while the gateway is still processing the update
+    pub fn is_final(&self) -> bool {
+        !matches!(self.status.as_str(), "pending" | "processing")
+    }
+
+    pub fn is_successful(&self) -> bool {
+        !matches!(self.status.as_str(), "failed" | "timeout") && self.errors.is_empty()
+    }
+}
+
+pub async fn esl_status(esl_server_url: &str, hanshow_user: String) -> Result<bool, HanshowError> {
+    let client = reqwest::Client::new();
+    let url = format!("{esl_server_url}/api2/{hanshow_user}/esls");
+    let response = client.get(url).send().await?;
+    Ok(response.status() == StatusCode::OK)
+}
+
+/// Fetches the result of an update request, returns None while the gateway does not know the sid yet
+pub async fn update_result(
+    request_status: &HanshowAccepted,
+    esl_server_url: &str,
+    hanshow_user: String,
+) -> Result<Option<HanshowUpdateResult>, HanshowError> {
+    let client = reqwest::Client::new();
+    let url = format!(
+        "{}/api2/{}/results/{}",
+        esl_server_url, hanshow_user, request_status.sid
+    );
+    let response = client.get(url).send().await?;
+    match response.status() {
+        StatusCode::OK => {
+            let body: HanshowResponse<HanshowUpdateResult> = response.json().await?;
+            match body.data {
+                Some(result) if body.is_accepted() => {
+                    debug!("Got update result: {}", result.status);
+                    Ok(Some(result))
+                }
+                _ => {
+                    debug!("Esl server has no result: errno={}", body.errno);
+                    Err(HanshowError::MissingResult {
+                        sid: request_status.sid.clone(),
+                    })
+                }
+            }
+        }
+        StatusCode::NOT_FOUND => {
+            debug!("Esl server has no result yet for {}", request_status.sid);
+            Ok(None)
+        }
+        _reqwest_error => {
+            debug!("Esl server denied the result: {}", response.status());
+            Err(HanshowError::MissingResult {
+                sid: request_status.sid.clone(),
+            })
+        }
+    }
+}
+
+/// Polls the result of an update request with a backoff until the gateway reports a final status or `timeout` expires
+pub async fn wait_update_result(
+    request_status: &HanshowAccepted,
+    esl_server_url: &str,
+    hanshow_user: String,
+    timeout: Duration,
+) -> Result<HanshowUpdateOutcome, HanshowError> {
+    let mut backoff = Backoff::new(timeout);
+    let mut last = None;
+    loop {
+        if let Some(result) =
+            update_result(request_status, esl_server_url, hanshow_user.clone()).await?
+        {
+            if result.is_final() {
+                return Ok(HanshowUpdateOutcome::Completed(result));
+            }
+            last = Some(result);
+        }
+        if !backoff.wait().await {
+            debug!("Timed out waiting for the result of {}", request_status.sid);
+            return Ok(HanshowUpdateOutcome::TimedOut(last));
+        }
+    }
+}
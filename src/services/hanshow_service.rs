@@ -0,0 +1,226 @@
+use std::{future::Future, io, time::Duration};
+
+use super::backend::{BackendError, EslBackend, UpdateOutcome, UpdateReport};
+use super::esl_service::HublotPrintRequest;
+use super::hanshow::{
+    bindings::map_esl_to_sku,
+    product::{update_products, HanshowAccepted},
+    status::{esl_status, wait_update_result, HanshowUpdateOutcome},
+};
+use super::metrics::METRICS;
+use crate::{settings::Settings, utils::unicode_string};
+use esl_utils::generic_esl::GenericEsl;
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+custom_error! {
+    /// An error that can occur while handling Hanshow Esls.
+    ///
+    /// This error can be seamlessly converted to an `io::Error` and `reqwest::Error` via a `From`
+    /// implementation.
+    pub HanshowError
+        Reqwest{source: reqwest::Error} = "An issue occured within this request: {source}",
+        Io{source: io::Error}= "An I/O error occured: {source}",
+        MissingBinding = "Cannot find a product bound to this esl",
+        MissingPosition{esl_id: String, position: i32} = "Esl {esl_id} has no product bound at displayPosition {position}",
+        UpdateFailed{sku: String, errno: i32} = "HanshowError, cannot update this product: {sku} (errno={errno})",
+        MissingResult{sid: String} = "HanshowError, no update result for this sid: {sid}"
+}
+
+/// The envelope used by every ESL-Working api2 response.
+///
+/// `errno` is 0 on success and 1 when the request has been queued by the gateway,
+/// any other value is an error described by `errmsg`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HanshowResponse<T> {
+    pub errno: i32,
+    pub errmsg: Option<String>,
+    pub data: Option<T>,
+}
+
+impl<T> HanshowResponse<T> {
+    pub fn is_success(&self) -> bool {
+        self.errno == 0
+    }
+
+    pub fn is_accepted(&self) -> bool {
+        self.errno == 0 || self.errno == 1
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HanshowFishProperties {
+    #[serde(with = "unicode_string")]
+    name: String,
+    #[serde(rename = "scientific_name", with = "unicode_string")]
+    scientific_name: String,
+    origin: Option<String>,
+    origin_2: Option<String>,
+    engin: Option<String>,
+    info: Option<String>,
+    production: Option<String>,
+    plu: Option<String>,
+    allergens: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HanshowEsl {
+    pub esl_id: String,
+    /// The Hanshow product code bound to `esl_id`
+    pub sku: String,
+    /// Serial id used by ESL-Working to track the update of this product
+    pub sid: String,
+    #[serde(flatten)]
+    pub properties: HanshowFishProperties,
+}
+
+impl From<GenericEsl> for HanshowEsl {
+    fn from(value: GenericEsl) -> Self {
+        let properties = HanshowFishProperties {
+            name: value.nom.clone(),
+            scientific_name: value.nom_scientifique,
+            // origin = the product was not fished therefore there is no fishing gear
+            engin: if value.origine.is_some() {
+                None
+            } else {
+                value.engin
+            },
+            info: value.congel_infos,
+            origin: Some(value.origine.unwrap_or(value.zone.unwrap_or_default())),
+            origin_2: Some(value.sous_zone.unwrap_or_default()),
+            production: value.production,
+            plu: Some(value.plu),
+            allergens: value.allergenes,
+        };
+        Self {
+            esl_id: value.id,
+            sku: value.object_id.unwrap_or_default(),
+            sid: uuid::Uuid::new_v4().to_string(),
+            properties,
+        }
+    }
+}
+
+/// The Hanshow ESL-Working api2, scoped to the store code `hanshow_user`
+#[derive(Debug, Clone)]
+pub struct HanshowBackend {
+    esl_server_url: String,
+    hanshow_user: String,
+    result_timeout: Duration,
+}
+
+/// Runs a call to the ESL-Working api and records it in the metrics under `stage`
+async fn record<T>(
+    stage: &'static str,
+    call: impl Future<Output = Result<T, HanshowError>>,
+) -> Result<T, HanshowError> {
+    let started = Instant::now();
+    let result = call.await;
+    METRICS.record_esl_call(stage, result.is_ok(), started.elapsed());
+    result
+}
+
+impl EslBackend for HanshowBackend {
+    const NAME: &'static str = "hanshow";
+    type Esl = HanshowEsl;
+    type Ticket = Vec<HanshowAccepted>;
+
+    fn from_settings(config: &Settings) -> Result<Self, BackendError> {
+        Ok(Self {
+            esl_server_url: config.esl_server_url.clone(),
+            hanshow_user: config
+                .hanshow_user
+                .clone()
+                .ok_or_else(|| BackendError::MissingKey {
+                    backend: Self::NAME.to_string(),
+                    key: "hanshow_user".to_string(),
+                })?,
+            result_timeout: Duration::from_millis(config.esl_result_timeout.unwrap_or(30000)),
+        })
+    }
+
+    fn missing_keys(config: &Settings) -> Vec<&'static str> {
+        if config.hanshow_user.is_none() {
+            vec!["hanshow_user"]
+        } else {
+            Vec::new()
+        }
+    }
+
+    async fn map(&self, request: &HublotPrintRequest) -> Result<Vec<HanshowEsl>, BackendError> {
+        Ok(record(
+            "map",
+            map_esl_to_sku(
+                request.esl.clone().into(),
+                request.target(),
+                &self.esl_server_url,
+                self.hanshow_user.clone(),
+            ),
+        )
+        .await?)
+    }
+
+    async fn update(&self, esls: &[HanshowEsl]) -> Result<Vec<HanshowAccepted>, BackendError> {
+        Ok(record(
+            "update",
+            update_products(esls, &self.esl_server_url, self.hanshow_user.clone()),
+        )
+        .await?)
+    }
+
+    async fn confirm(
+        &self,
+        tickets: Vec<HanshowAccepted>,
+        _esls: &[HanshowEsl],
+    ) -> Result<Vec<UpdateReport>, BackendError> {
+        // every product has its own sid, listed in the same order as the esls
+        let mut reports = Vec::with_capacity(tickets.len());
+        // the whole chunk shares one deadline, a stalled sid leaves less time to the next ones
+        let deadline = Instant::now() + self.result_timeout;
+        for ticket in tickets {
+            let outcome = record(
+                "confirm",
+                wait_update_result(
+                    &ticket,
+                    &self.esl_server_url,
+                    self.hanshow_user.clone(),
+                    deadline.saturating_duration_since(Instant::now()),
+                ),
+            )
+            .await;
+            let report = match outcome {
+                Ok(HanshowUpdateOutcome::Completed(result)) => UpdateReport {
+                    outcome: if result.is_successful() {
+                        UpdateOutcome::Succeeded
+                    } else {
+                        UpdateOutcome::Failed
+                    },
+                    request_id: result.sid,
+                    status: result.status,
+                    errors: result.errors,
+                },
+                Ok(HanshowUpdateOutcome::TimedOut(last)) => UpdateReport {
+                    request_id: ticket.sid,
+                    status: last.map_or("pending".to_string(), |result| result.status),
+                    errors: Vec::new(),
+                    outcome: UpdateOutcome::TimedOut,
+                },
+                Err(HanshowError::Reqwest { source }) if source.is_builder() => {
+                    return Err(HanshowError::Reqwest { source }.into())
+                }
+                Err(e) => UpdateReport {
+                    request_id: ticket.sid,
+                    status: "unknown".to_string(),
+                    errors: vec![e.to_string()],
+                    outcome: UpdateOutcome::Failed,
+                },
+            };
+            reports.push(report);
+        }
+        Ok(reports)
+    }
+
+    async fn health(&self) -> Result<bool, BackendError> {
+        Ok(esl_status(&self.esl_server_url, self.hanshow_user.clone()).await?)
+    }
+}
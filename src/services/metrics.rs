@@ -0,0 +1,134 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use chrono::Local;
+use lazy_static::lazy_static;
+use serde::Serialize;
+
+lazy_static! {
+    /// The counters of the running app, exposed by the monitoring server
+    pub static ref METRICS: Metrics = Metrics::default();
+}
+
+/// The outcome of the last call to a server
+#[derive(Serialize, Clone, Debug)]
+pub struct LastCall {
+    pub success: bool,
+    /// RFC 3339 date of the call
+    pub at: String,
+}
+
+#[derive(Default, Clone, Copy)]
+struct Latency {
+    count: u64,
+    failures: u64,
+    sum: Duration,
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    polls: AtomicU64,
+    poll_failures: AtomicU64,
+    /// The final outcome of every print request, by outcome
+    updates: Mutex<BTreeMap<&'static str, u64>>,
+    /// The duration of every stage of the update of a print request, by stage
+    latencies: Mutex<BTreeMap<&'static str, Latency>>,
+    last_poll: Mutex<Option<LastCall>>,
+    last_esl_call: Mutex<Option<LastCall>>,
+}
+
+impl Metrics {
+    /// Records a poll of the Hublot server
+    pub fn record_poll(&self, success: bool) {
+        self.polls.fetch_add(1, Ordering::Relaxed);
+        if !success {
+            self.poll_failures.fetch_add(1, Ordering::Relaxed);
+        }
+        *self.last_poll.lock().unwrap() = Some(LastCall {
+            success,
+            at: Local::now().to_rfc3339(),
+        });
+    }
+
+    /// Records a call to the esl server, `stage` being one of map, update or confirm
+    pub fn record_esl_call(&self, stage: &'static str, success: bool, elapsed: Duration) {
+        let mut latencies = self.latencies.lock().unwrap();
+        let latency = latencies.entry(stage).or_default();
+        latency.count += 1;
+        latency.sum += elapsed;
+        if !success {
+            latency.failures += 1;
+        }
+        *self.last_esl_call.lock().unwrap() = Some(LastCall {
+            success,
+            at: Local::now().to_rfc3339(),
+        });
+    }
+
+    /// Records the final outcome of a print request
+    pub fn record_update(&self, outcome: &'static str) {
+        *self.updates.lock().unwrap().entry(outcome).or_insert(0) += 1;
+    }
+
+    pub fn last_poll(&self) -> Option<LastCall> {
+        self.last_poll.lock().unwrap().clone()
+    }
+
+    pub fn last_esl_call(&self) -> Option<LastCall> {
+        self.last_esl_call.lock().unwrap().clone()
+    }
+
+    /// Renders every counter in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "# HELP esl_polls_total Number of polls of the Hublot server\n# TYPE esl_polls_total counter\nesl_polls_total {}",
+            self.polls.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "# HELP esl_poll_failures_total Number of failed polls of the Hublot server\n# TYPE esl_poll_failures_total counter\nesl_poll_failures_total {}",
+            self.poll_failures.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "# HELP esl_updates_total Number of print requests that reached a final outcome\n# TYPE esl_updates_total counter"
+        );
+        for (outcome, count) in self.updates.lock().unwrap().iter() {
+            let _ = writeln!(out, "esl_updates_total{{outcome=\"{outcome}\"}} {count}");
+        }
+        let _ = writeln!(
+            out,
+            "# HELP esl_stage_duration_seconds Time spent calling the esl server, by stage\n# TYPE esl_stage_duration_seconds summary"
+        );
+        let latencies = self.latencies.lock().unwrap();
+        for (stage, latency) in latencies.iter() {
+            let _ = writeln!(
+                out,
+                "esl_stage_duration_seconds_sum{{stage=\"{stage}\"}} {}\nesl_stage_duration_seconds_count{{stage=\"{stage}\"}} {}",
+                latency.sum.as_secs_f64(),
+                latency.count
+            );
+        }
+        let _ = writeln!(
+            out,
+            "# HELP esl_stage_failures_total Number of failed calls to the esl server, by stage\n# TYPE esl_stage_failures_total counter"
+        );
+        for (stage, latency) in latencies.iter() {
+            let _ = writeln!(
+                out,
+                "esl_stage_failures_total{{stage=\"{stage}\"}} {}",
+                latency.failures
+            );
+        }
+        out
+    }
+}
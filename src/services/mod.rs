@@ -1,13 +1,24 @@
+pub mod activity;
+pub mod admin;
+pub mod backend;
+pub mod control;
 pub mod esl_service;
+pub mod hanshow;
+pub mod hanshow_service;
+pub mod metrics;
+pub mod monitoring;
+pub mod outbox;
 pub mod parse_log;
 pub mod poll;
 pub mod pricer;
 pub mod pricer_service;
 use custom_error::custom_error;
-use log::debug;
+use log::{debug, warn};
 use reqwest::{Certificate, Client, ClientBuilder, Identity, Proxy};
 use std::io::Read;
-use std::{fs::File, io};
+use std::{fs::File, io, time::Duration};
+
+use crate::settings::PricerSettings;
 
 custom_error! {
     /// An error that can occur when building our Api client.
@@ -18,7 +29,8 @@ custom_error! {
         Reqwest{source: reqwest::Error} = "Unable to build a reqwest client: {source}",
         Io{source: io::Error}= "unable to read from the file: {source}"
 }
-static APP_USER_AGENT: &str = concat!(
+/// The name and version of the broker
+pub static APP_USER_AGENT: &str = concat!(
     env!("CARGO_PKG_NAME"),
     "/",
     env!("CARGO_PKG_VERSION"),
@@ -35,9 +47,10 @@ fn get_identity(pem_content: Vec<u8>, pkcs8: Vec<u8>) -> Result<Identity, Client
     Ok(Identity::from_pkcs8_pem(&pem_content, &pkcs8)?)
 }
 #[cfg(feature = "rustls-tls")]
-fn get_identity(pem_content: Vec<u8>, _pkcs8: Vec<u8>) -> Result<Identity, ClientError> {
+fn get_identity(pem_content: Vec<u8>, pkcs8: Vec<u8>) -> Result<Identity, ClientError> {
     debug!("Using rustls as tls implementation");
-    Ok(Identity::from_pem(&pem_content)?)
+    // rustls reads the certificate and its key from a single pem
+    Ok(Identity::from_pem(&[pem_content, pkcs8].concat())?)
 }
 
 /// Builds a configured reqwest http client.
@@ -81,3 +94,44 @@ pub fn build_client(
     let client = client_builder.build()?;
     Ok(client)
 }
+
+/// Builds the reqwest http client used to reach the Pricer server.
+///
+/// The client is configured by the `[pricer]` section with its own proxy, certificates and timeouts.
+pub fn build_pricer_client(config: &PricerSettings) -> Result<Client, ClientError> {
+    let mut client_builder = ClientBuilder::new()
+        .user_agent(APP_USER_AGENT)
+        .timeout(Duration::from_millis(config.timeout.unwrap_or(30000)))
+        .connect_timeout(Duration::from_millis(
+            config.connect_timeout.unwrap_or(10000),
+        ));
+    if let Some(cs) = &config.proxy_cs {
+        debug!("Pricer config contains a proxy connection string, adding it to the http client");
+        client_builder = client_builder.proxy(Proxy::all(cs)?);
+    }
+    if let Some(root) = &config.root_certificate_path {
+        debug!("Pricer config contains a root certificate, adding it to the http client");
+        let cert = Certificate::from_pem(&read_certificate(root)?)?;
+        client_builder = client_builder.add_root_certificate(cert);
+    }
+    if let (Some(certificate), Some(key)) =
+        (&config.client_certificate_path, &config.client_key_path)
+    {
+        debug!("Pricer config contains a client certificate, adding it to the http client");
+        #[cfg(feature = "rustls-tls")]
+        {
+            client_builder = client_builder.use_rustls_tls();
+        }
+        #[cfg(not(feature = "rustls-tls"))]
+        {
+            client_builder = client_builder.use_native_tls();
+        }
+        let identity = get_identity(read_certificate(certificate)?, read_certificate(key)?)?;
+        client_builder = client_builder.identity(identity);
+    }
+    if config.accept_invalid_certs.unwrap_or(false) {
+        warn!("The certificate of the Pricer server will not be verified");
+        client_builder = client_builder.danger_accept_invalid_certs(true);
+    }
+    Ok(client_builder.build()?)
+}
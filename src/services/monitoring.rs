@@ -0,0 +1,141 @@
+use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use log::{debug, info};
+use reqwest::Client;
+use serde_json::json;
+
+use super::{
+    backend::EslBackend,
+    build_client, esl_service,
+    metrics::{LastCall, METRICS},
+    pricer_service::{self, PricerBackend},
+    ClientError,
+};
+use crate::{settings::Settings, utils::circuit_breaker::CircuitState};
+
+custom_error! {
+    /// An error that can occur while running the monitoring server.
+    ///
+    /// This error can be seamlessly converted to a `hyper::Error` and `AddrParseError` via a `From`
+    /// implementation.
+    pub MonitoringError
+        Hyper{source: hyper::Error} = "The monitoring server has failed: {source}",
+        AddrParse{source: std::net::AddrParseError} = "Invalid monitoring_addr in hublot-config.toml: {source}",
+        ClientError{source: ClientError} = "An issue occured with the ClientBuilder: {source}"
+}
+
+struct MonitoringState {
+    config: Settings,
+    hublot_client: Client,
+}
+
+/// Serves `/healthz`, `/readyz` and `/metrics` on `addr` until the process stops
+pub async fn serve(addr: &str, config: Settings) -> Result<(), MonitoringError> {
+    let addr: SocketAddr = addr.parse()?;
+    let hublot_client = build_client(
+        config.proxy_cs.clone(),
+        config.certificate_pem_path.clone(),
+        config.certificate_root_path.clone(),
+        config.certificate_key_path.clone(),
+    )?;
+    let state = Arc::new(MonitoringState {
+        config,
+        hublot_client,
+    });
+    let make_service = make_service_fn(move |_conn| {
+        let state = state.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |request| route(request, state.clone()))) }
+    });
+    let server = Server::try_bind(&addr)?.serve(make_service);
+    info!("Monitoring server listening on http://{}", addr);
+    Ok(server.await?)
+}
+
+async fn route(
+    request: Request<Body>,
+    state: Arc<MonitoringState>,
+) -> Result<Response<Body>, Infallible> {
+    debug!("Monitoring request: {} {}", request.method(), request.uri());
+    let response = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/healthz") => healthz(),
+        (&Method::GET, "/readyz") => readyz(&state).await,
+        (&Method::GET, "/metrics") => Response::builder()
+            .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+            .body(Body::from(METRICS.render())),
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty()),
+    };
+    Ok(response.unwrap_or_else(|_| {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+        response
+    }))
+}
+
+fn json_response(healthy: bool, body: serde_json::Value) -> hyper::http::Result<Response<Body>> {
+    Response::builder()
+        .status(if healthy {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        })
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+}
+
+fn failed(call: &Option<LastCall>) -> bool {
+    call.as_ref().is_some_and(|call| !call.success)
+}
+
+/// Healthy unless the last Hublot poll or the last esl server call has failed
+fn healthz() -> hyper::http::Result<Response<Body>> {
+    let last_poll = METRICS.last_poll();
+    let last_esl_call = METRICS.last_esl_call();
+    let healthy = !failed(&last_poll) && !failed(&last_esl_call);
+    json_response(
+        healthy,
+        json!({
+            "healthy": healthy,
+            "lastPoll": last_poll,
+            "lastEslCall": last_esl_call,
+        }),
+    )
+}
+
+/// Ready once the Hublot server is reachable and the esl server is not paused.
+///
+/// The esl server is not called, it is judged by the live circuit breaker of the Pricer server,
+/// or by the last call of the polling worker for the vendors without a breaker.
+async fn readyz(state: &MonitoringState) -> hyper::http::Result<Response<Body>> {
+    let hublot = esl_service::status(&state.config.hublot_server_url, &state.hublot_client)
+        .await
+        .unwrap_or(false);
+    let pricer = state
+        .config
+        .esl_vendor
+        .as_deref()
+        .unwrap_or(PricerBackend::NAME)
+        == PricerBackend::NAME;
+    let circuit = pricer.then(pricer_service::circuit_state).flatten();
+    let last_esl_call = METRICS.last_esl_call();
+    let esl_server = match circuit {
+        Some(circuit) => circuit != CircuitState::Open,
+        None => !failed(&last_esl_call),
+    };
+    json_response(
+        hublot && esl_server,
+        json!({
+            "ready": hublot && esl_server,
+            "hublot": hublot,
+            "eslServer": esl_server,
+            "circuitBreaker": circuit,
+            "lastEslCall": last_esl_call,
+        }),
+    )
+}
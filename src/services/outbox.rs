@@ -0,0 +1,181 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use super::esl_service::HublotPrintRequest;
+use crate::settings::{Settings, DEFAULT_LOG_FILE};
+
+/// A print request waiting for its final outcome
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct OutboxEntry {
+    pub request: HublotPrintRequest,
+    /// The update attempts already made for this request
+    pub attempts: u32,
+}
+
+/// An on-disk journal of the print requests that did not reach a final outcome yet.
+///
+/// Every request is stored in its own file, written to a temporary file then renamed so that
+/// a crash never leaves a truncated entry behind.
+pub struct Outbox {
+    dir: PathBuf,
+}
+
+impl Outbox {
+    /// Opens the outbox set by `outbox_dir`, an `outbox` directory next to the log file by default.
+    ///
+    /// When the directory cannot be created the requests are only kept in memory, every journal
+    /// write is then reported by the caller.
+    pub fn open(config: &Settings) -> Self {
+        let dir = match &config.outbox_dir {
+            Some(dir) => PathBuf::from(dir),
+            None => Path::new(config.log_file.as_deref().unwrap_or(DEFAULT_LOG_FILE))
+                .parent()
+                .unwrap_or(Path::new("."))
+                .join("outbox"),
+        };
+        match fs::create_dir_all(&dir) {
+            Ok(()) => debug!("Using the outbox directory {:?}", dir),
+            Err(e) => warn!("Cannot create the outbox directory {:?}: {}", dir, e),
+        }
+        Self { dir }
+    }
+
+    fn path(&self, esl_id: &str) -> PathBuf {
+        // esl ids are barcodes, but they end up in a file name
+        let name: String = esl_id
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        // the hash of the raw id keeps apart the ids that are sanitized to the same name
+        self.dir.join(format!("{name}-{:016x}.json", fnv1a(esl_id)))
+    }
+
+    /// Returns every pending entry, unreadable entries are skipped
+    pub fn load(&self) -> Vec<OutboxEntry> {
+        let mut entries = Vec::new();
+        let files = match fs::read_dir(&self.dir) {
+            Ok(files) => files,
+            Err(e) => {
+                warn!("Cannot read the outbox directory {:?}: {}", self.dir, e);
+                return entries;
+            }
+        };
+        for file in files {
+            let path = match file {
+                Ok(file) => file.path(),
+                Err(e) => {
+                    warn!("Cannot read the outbox directory {:?}: {}", self.dir, e);
+                    continue;
+                }
+            };
+            if path.extension().and_then(|extension| extension.to_str()) != Some("json") {
+                continue;
+            }
+            match fs::read(&path).map(|content| serde_json::from_slice::<OutboxEntry>(&content)) {
+                Ok(Ok(entry)) => entries.push(entry),
+                Ok(Err(e)) => warn!("Skipping the corrupted outbox entry {:?}: {}", path, e),
+                Err(e) => warn!("Cannot read the outbox entry {:?}: {}", path, e),
+            }
+        }
+        entries
+    }
+
+    /// Journals `request`, replacing the pending entry of the same esl
+    pub fn push(&self, request: &HublotPrintRequest, attempts: u32) -> io::Result<()> {
+        let entry = OutboxEntry {
+            request: request.clone(),
+            attempts,
+        };
+        let path = self.path(request.id());
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec(&entry)?)?;
+        fs::rename(tmp, path)
+    }
+
+    /// Drops the entry of `esl_id` once its print request reached a final outcome
+    pub fn done(&self, esl_id: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(esl_id)) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+}
+
+/// The 64 bits FNV-1a hash of `value`, stable across builds unlike the `std` hasher
+fn fnv1a(value: &str) -> u64 {
+    value.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn outbox() -> Outbox {
+        let dir = std::env::temp_dir().join(format!("outbox-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        Outbox { dir }
+    }
+
+    fn request(barcode: &str) -> HublotPrintRequest {
+        serde_json::from_value(json!({
+            "id": barcode,
+            "nom": "Bar",
+            "nom_scientifique": "Dicentrarchus labrax",
+            "plu": "1234",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn push_replaces_the_entry_of_the_same_esl() {
+        let outbox = outbox();
+        outbox.push(&request("0001"), 1).unwrap();
+        outbox.push(&request("0001"), 2).unwrap();
+        let entries = outbox.load();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].attempts, 2);
+        fs::remove_dir_all(&outbox.dir).unwrap();
+    }
+
+    #[test]
+    fn ids_sanitized_to_the_same_name_are_kept_apart() {
+        let outbox = outbox();
+        outbox.push(&request("a/b"), 1).unwrap();
+        outbox.push(&request("a_b"), 1).unwrap();
+        assert_eq!(outbox.load().len(), 2);
+        outbox.done("a/b").unwrap();
+        let entries = outbox.load();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].request.id(), "a_b");
+        fs::remove_dir_all(&outbox.dir).unwrap();
+    }
+
+    #[test]
+    fn load_skips_an_unreadable_directory() {
+        let outbox = Outbox {
+            dir: std::env::temp_dir().join(format!("outbox-{}", uuid::Uuid::new_v4())),
+        };
+        assert!(outbox.load().is_empty());
+    }
+
+    #[test]
+    fn done_ignores_unknown_ids() {
+        let outbox = outbox();
+        outbox.done("unknown").unwrap();
+        fs::remove_dir_all(&outbox.dir).unwrap();
+    }
+}
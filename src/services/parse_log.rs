@@ -1,4 +1,52 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use esl_utils::parse::ParseClient;
+use lazy_static::lazy_static;
+use log::{debug, warn, Level, Record};
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::{interval, MissedTickBehavior},
+};
+
+use super::APP_USER_AGENT;
+use crate::settings::{Settings, DEFAULT_LOG_FILE};
+
+/// Maximum number of logs waiting to be shipped, the logs are dropped beyond
+const QUEUE_SIZE: usize = 1000;
+
+/// Once this many slots are left in the queue, only the warnings and errors are kept
+const LOW_PRIORITY_SLOTS: usize = QUEUE_SIZE / 4;
+
+/// Maximum number of logs sent to Parse in a single request
+const BATCH_SIZE: usize = 50;
+
+/// Number of queued logs in a batch, the last slot is kept for the warning about the dropped logs
+const LOGS_PER_BATCH: usize = BATCH_SIZE - 1;
+
+/// Time between two requests to Parse, which caps the logs shipped to `BATCH_SIZE` per interval
+const SEND_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Maximum size in bytes of the spool holding the logs while Parse is unreachable
+const MAX_SPOOL_SIZE: u64 = 10 * 1024 * 1024;
+
+lazy_static! {
+    /// Identifies the current run of the broker in the shipped logs
+    static ref SESSION: String = uuid::Uuid::new_v4().to_string();
+    static ref HOSTNAME: String = hostname::get()
+        .map(|hostname| hostname.to_string_lossy().into_owned())
+        .unwrap_or_default();
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ParseLog {
@@ -7,4 +55,420 @@ pub struct ParseLog {
     pub level: String,
     pub message: String,
     pub serial: String,
+    /// The module that emitted the log
+    pub module: Option<String>,
+    /// The barcode of the ESL the log is about
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub barcode: Option<String>,
+    /// The Pricer item of the ESL
+    #[serde(rename = "itemId", skip_serializing_if = "Option::is_none")]
+    pub item_id: Option<String>,
+    /// The update request on the esl server
+    #[serde(rename = "requestId", skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// The objectId of the print request on the Hublot server
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rid: Option<String>,
+    /// The version of the broker, as sent in its user agent
+    pub version: String,
+    /// Identifies the run of the broker that emitted the log
+    pub session: String,
+    /// The host running the broker, empty when it cannot be read
+    pub hostname: String,
+}
+
+impl ParseLog {
+    /// Builds the log of `record`, along with its ESL `context` and the session metadata
+    pub fn new(record: &Record, serial: &str, context: &Map<String, Value>) -> Self {
+        // the requestId is a number for Pricer and a string for Hanshow
+        let field = |key: &str| {
+            context.get(key).map(|value| match value {
+                Value::String(value) => value.clone(),
+                value => value.to_string(),
+            })
+        };
+        Self {
+            app: "hublot-esl-backend".to_string(),
+            level: record.level().to_string(),
+            message: record.args().to_string(),
+            serial: serial.to_string(),
+            module: record.module_path().map(str::to_string),
+            barcode: field("barcode"),
+            item_id: field("itemId"),
+            request_id: field("requestId"),
+            rid: field("rid"),
+            version: APP_USER_AGENT.to_string(),
+            session: SESSION.clone(),
+            hostname: HOSTNAME.clone(),
+        }
+    }
+}
+
+/// A single create request of a Parse batch
+#[derive(Serialize)]
+struct BatchRequest<'a> {
+    method: &'static str,
+    path: &'static str,
+    body: &'a ParseLog,
+}
+
+#[derive(Serialize)]
+struct Batch<'a> {
+    requests: Vec<BatchRequest<'a>>,
+}
+
+/// Returns true when the logs of `target` are shipped to Parse, the logs of the shipper itself are not
+pub fn is_shipped(target: &str) -> bool {
+    target.contains("esl_services_backend") && target != module_path!()
+}
+
+/// Ships the logs to Parse in batches from a background task.
+///
+/// The logs wait in a bounded queue: once it is nearly full the debug and info logs are dropped,
+/// then every log. The batches that cannot be sent are spooled to disk and replayed once Parse
+/// is reachable again.
+#[derive(Clone)]
+pub struct LogShipper {
+    sender: mpsc::Sender<ParseLog>,
+    flush: mpsc::Sender<oneshot::Sender<()>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl LogShipper {
+    /// Starts the shipper task, the spool is kept next to the log file
+    pub fn start(parse_client: ParseClient, config: &Settings) -> Self {
+        let spool = Path::new(config.log_file.as_deref().unwrap_or(DEFAULT_LOG_FILE))
+            .parent()
+            .unwrap_or(Path::new("."))
+            .join("parse-spool.jsonl");
+        let offset = spool.with_extension("offset");
+        // the logs replayed by the previous run before it stopped
+        let replayed = fs::read_to_string(&offset)
+            .ok()
+            .and_then(|replayed| replayed.trim().parse().ok())
+            .unwrap_or(0);
+        let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+        let (flush, flush_requests) = mpsc::channel(1);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let task = ShipperTask {
+            parse_client,
+            spool,
+            offset,
+            replayed,
+            dropped: dropped.clone(),
+        };
+        tokio::task::spawn(task.run(receiver, flush_requests));
+        Self {
+            sender,
+            flush,
+            dropped,
+        }
+    }
+
+    /// Queues `log` without blocking, it is dropped when the queue is full
+    pub fn send(&self, log: ParseLog) {
+        let level = log.level.parse().unwrap_or(Level::Info);
+        let low_priority = level > Level::Warn;
+        if low_priority && self.sender.capacity() <= LOW_PRIORITY_SLOTS {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        if self.sender.try_send(log).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Sends or spools every queued log, returns false when it did not finish within `timeout`
+    pub async fn flush(&self, timeout: Duration) -> bool {
+        let flushed = async {
+            let (done, flushed) = oneshot::channel();
+            self.flush.send(done).await.ok()?;
+            flushed.await.ok()
+        };
+        matches!(tokio::time::timeout(timeout, flushed).await, Ok(Some(())))
+    }
+}
+
+struct ShipperTask {
+    parse_client: ParseClient,
+    spool: PathBuf,
+    /// Keeps `replayed` across runs, so that a crash does not send the replayed logs again
+    offset: PathBuf,
+    /// The byte offset of the first spooled log that has not been replayed yet
+    replayed: u64,
+    dropped: Arc<AtomicU64>,
+}
+
+impl ShipperTask {
+    async fn run(
+        mut self,
+        mut receiver: mpsc::Receiver<ParseLog>,
+        mut flush_requests: mpsc::Receiver<oneshot::Sender<()>>,
+    ) {
+        let mut batch = Vec::new();
+        let mut ticker = interval(SEND_INTERVAL);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                // a full batch waits for the next tick, the queue fills up meanwhile
+                log = receiver.recv(), if batch.len() < LOGS_PER_BATCH => match log {
+                    Some(log) => batch.push(log),
+                    None => {
+                        self.ship(batch).await;
+                        self.compact_spool();
+                        return;
+                    }
+                },
+                Some(done) = flush_requests.recv() => {
+                    while let Ok(log) = receiver.try_recv() {
+                        batch.push(log);
+                    }
+                    self.drain(std::mem::take(&mut batch)).await;
+                    self.compact_spool();
+                    let _ = done.send(());
+                },
+                _ = ticker.tick() => {
+                    if self.ship(std::mem::take(&mut batch)).await {
+                        self.replay_spool().await;
+                    }
+                },
+            }
+        }
+    }
+
+    /// Sends every log of `logs` regardless of the rate limit, once Parse fails the rest is spooled
+    async fn drain(&self, mut logs: Vec<ParseLog>) {
+        while !logs.is_empty() {
+            let rest = logs.split_off(logs.len().min(LOGS_PER_BATCH));
+            if !self.ship(logs).await {
+                if let Err(e) = self.spool(&rest) {
+                    warn!("Cannot spool {} logs, they are lost: {}", rest.len(), e);
+                }
+                return;
+            }
+            logs = rest;
+        }
+    }
+
+    /// Sends `batch` to Parse or spools it, returns false when Parse could not be reached
+    async fn ship(&self, mut batch: Vec<ParseLog>) -> bool {
+        if batch.is_empty() {
+            return true;
+        }
+        let dropped = self.dropped.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            if let Some(first) = batch.first() {
+                batch.push(ParseLog {
+                    level: Level::Warn.to_string(),
+                    message: format!("{dropped} logs were dropped, the log queue was full"),
+                    module: Some(module_path!().to_string()),
+                    barcode: None,
+                    item_id: None,
+                    request_id: None,
+                    rid: None,
+                    ..first.clone()
+                });
+            }
+        }
+        match self.upload(&batch).await {
+            Ok(()) => true,
+            Err(e) => {
+                debug!(
+                    "Cannot ship {} logs to Parse, spooling them: {}",
+                    batch.len(),
+                    e
+                );
+                if let Err(e) = self.spool(&batch) {
+                    warn!("Cannot spool {} logs, they are lost: {}", batch.len(), e);
+                }
+                false
+            }
+        }
+    }
+
+    async fn upload(&self, batch: &[ParseLog]) -> Result<(), reqwest::Error> {
+        let requests = batch
+            .iter()
+            .map(|body| BatchRequest {
+                method: "POST",
+                path: "/parse/classes/Log",
+                body,
+            })
+            .collect();
+        self.parse_client
+            .clone()
+            .save("parse/batch".to_string(), Batch { requests })
+            .await?;
+        Ok(())
+    }
+
+    fn spool(&self, batch: &[ParseLog]) -> io::Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let size = fs::metadata(&self.spool).map_or(0, |meta| meta.len());
+        if size.saturating_sub(self.replayed) > MAX_SPOOL_SIZE {
+            self.dropped
+                .fetch_add(batch.len() as u64, Ordering::Relaxed);
+            return Ok(());
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.spool)?;
+        for log in batch {
+            writeln!(file, "{}", serde_json::to_string(log)?)?;
+        }
+        Ok(())
+    }
+
+    /// Sends the oldest spooled batch, now that Parse is reachable again.
+    ///
+    /// The spool is read from the `replayed` offset, it is removed once every log has been replayed.
+    async fn replay_spool(&mut self) {
+        let Ok(size) = fs::metadata(&self.spool).map(|meta| meta.len()) else {
+            self.set_replayed(0);
+            return;
+        };
+        if self.replayed >= size {
+            // the spool was replaced meanwhile when it is shorter than the offset
+            let replaced = self.replayed > size;
+            self.set_replayed(0);
+            if !replaced {
+                let _ = fs::remove_file(&self.spool);
+            }
+            return;
+        }
+        let (batch, read) = match self.read_spool() {
+            Ok(read) => read,
+            Err(e) => {
+                warn!("Cannot read the log spool {:?}: {}", self.spool, e);
+                return;
+            }
+        };
+        if !batch.is_empty() && self.upload(&batch).await.is_err() {
+            return;
+        }
+        debug!("Replayed {} spooled logs", batch.len());
+        if self.replayed + read >= size {
+            // the offset goes first, a crash in between replays the whole spool rather than skipping it
+            self.set_replayed(0);
+            if let Err(e) = fs::remove_file(&self.spool) {
+                warn!("Cannot remove the log spool {:?}: {}", self.spool, e);
+            }
+        } else {
+            self.set_replayed(self.replayed + read);
+        }
+    }
+
+    /// Moves the replay offset, and keeps it on disk for the next run
+    fn set_replayed(&mut self, replayed: u64) {
+        self.replayed = replayed;
+        let result = if replayed == 0 {
+            match fs::remove_file(&self.offset) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+                result => result,
+            }
+        } else {
+            fs::write(&self.offset, replayed.to_string())
+        };
+        if let Err(e) = result {
+            warn!(
+                "Cannot update the log spool offset {:?}: {}",
+                self.offset, e
+            );
+        }
+    }
+
+    /// Reads up to `BATCH_SIZE` logs from the `replayed` offset, along with the number of bytes read
+    fn read_spool(&self) -> io::Result<(Vec<ParseLog>, u64)> {
+        let mut file = File::open(&self.spool)?;
+        file.seek(SeekFrom::Start(self.replayed))?;
+        let mut reader = BufReader::new(file);
+        let mut batch = Vec::new();
+        let mut read = 0;
+        let mut line = String::new();
+        while batch.len() < BATCH_SIZE {
+            line.clear();
+            let length = reader.read_line(&mut line)?;
+            if length == 0 {
+                break;
+            }
+            read += length as u64;
+            if let Ok(log) = serde_json::from_str(&line) {
+                batch.push(log);
+            }
+        }
+        Ok((batch, read))
+    }
+
+    /// Removes the replayed logs from the spool, so that it does not grow while Parse is reachable
+    fn compact_spool(&mut self) {
+        if self.replayed == 0 {
+            return;
+        }
+        let spooled = match fs::read(&self.spool) {
+            Ok(spooled) => spooled,
+            Err(e) => {
+                warn!("Cannot read the log spool {:?}: {}", self.spool, e);
+                return;
+            }
+        };
+        let rest = spooled
+            .get(self.replayed as usize..)
+            .unwrap_or_default()
+            .to_vec();
+        self.set_replayed(0);
+        let result = if rest.is_empty() {
+            fs::remove_file(&self.spool)
+        } else {
+            fs::write(&self.spool, rest)
+        };
+        if let Err(e) = result {
+            warn!("Cannot update the log spool {:?}: {}", self.spool, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task() -> ShipperTask {
+        let dir = std::env::temp_dir().join(format!("parse-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let spool = dir.join("parse-spool.jsonl");
+        ShipperTask {
+            parse_client: ParseClient::new(String::new(), None, String::new()),
+            offset: spool.with_extension("offset"),
+            spool,
+            replayed: 0,
+            dropped: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    #[test]
+    fn keeps_the_replay_offset_on_disk() {
+        let mut task = task();
+        task.set_replayed(42);
+        assert_eq!(fs::read_to_string(&task.offset).unwrap(), "42");
+        task.set_replayed(0);
+        assert!(!task.offset.exists());
+        fs::remove_dir_all(task.spool.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn compacting_drops_the_replayed_logs() {
+        let mut task = task();
+        fs::write(&task.spool, "replayed\nwaiting\n").unwrap();
+        task.set_replayed("replayed\n".len() as u64);
+        task.compact_spool();
+        assert_eq!(fs::read_to_string(&task.spool).unwrap(), "waiting\n");
+        assert_eq!(task.replayed, 0);
+        assert!(!task.offset.exists());
+        task.set_replayed("waiting\n".len() as u64);
+        task.compact_spool();
+        assert!(!task.spool.exists());
+        fs::remove_dir_all(task.spool.parent().unwrap()).unwrap();
+    }
 }
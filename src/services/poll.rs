@@ -1,13 +1,21 @@
-use super::{esl_service::EslServiceError, pricer_service::PricerError};
-use crate::services::{
-    esl_service::get_print_requests,
-    pricer_service::{self, PricerEsl},
+use super::{
+    activity::{ActivityState, ACTIVITY},
+    backend::{self, BackendError, EslBackend, UpdateOutcome},
+    control::CONTROL,
+    esl_service::EslServiceError,
+    metrics::METRICS,
+    outbox::Outbox,
 };
+use crate::services::esl_service::{
+    acknowledge, get_print_requests, HublotPrintRequest, Origin, PrintAcknowledgement,
+};
+use crate::settings::Settings;
+use crate::utils::progress::status;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use log::info;
+use log::{debug, error, info, warn, Level};
 use reqwest::Client;
-use std::{io, time::Duration};
-use tokio::time::sleep;
+use std::{collections::HashMap, io, time::Duration};
+use tokio::{sync::watch, time::sleep};
 custom_error! {
     /// An error that can occur when during the API.
     ///
@@ -16,79 +24,298 @@ custom_error! {
     pub PollingError
         EslServiceError{source: EslServiceError} = "An issue occured calling the EslService: {source}",
         Reqwest{source: reqwest::Error} = "An issue occured within this request: {source}",
-        PricerError{source: PricerError} = "An issue occured calling the PricerService: {source}",
+        BackendError{source: BackendError} = "An issue occured calling the EslBackend: {source}",
         Io{source: io::Error}= "An I/O error occured: {source}",
 }
 
 /// A polling workser that fetches the `server_url` every `poll_interval`.
 ///
 /// If the server response is not empty, the worker will send a request to the `esl_server` in order
-/// to update the display of the ESLs
-pub async fn poll(
-    client_serial: &str,
-    hublot_server_url: &str,
-    esl_server_url: &str,
-    pricer_user: String,
-    pricer_password: String,
+/// to update the display of the ESLs.
+///
+/// Print requests are sent to the `esl_server` in chunks of `esl_batch_size` items.
+/// A print request that fails is retried on its own during the next cycles, up to `max_esl_attempts`,
+/// only fatal errors stop the worker.
+///
+/// Pending print requests are journaled in the `Outbox` until they reach a final outcome, the
+/// requests left over by a previous run are replayed first. The print requests pushed through the
+/// admin api are neither journaled nor acknowledged to the Hublot server.
+///
+/// The worker returns once `reload` holds a new configuration, so it can be restarted with it, the
+/// pending requests are replayed from the outbox.
+///
+/// While `CONTROL` is paused the Hublot server is not polled and the ESLs are not updated.
+///
+/// Once `shutdown` is set the worker stops polling and starting chunks, the chunk being sent is
+/// still confirmed until `main` gives up after `shutdown_timeout`. The requests left are kept in
+/// the outbox for the next run.
+pub async fn poll<B: EslBackend>(
+    backend: B,
+    config: &Settings,
     client: Client,
-    polling_rate: Option<i32>,
+    reload: &watch::Receiver<Settings>,
+    shutdown: &watch::Receiver<bool>,
 ) -> Result<(), PollingError> {
-    let interval = polling_rate.unwrap_or(2000);
+    let client_serial = config.client_serial.as_str();
+    let hublot_server_url = config.hublot_server_url.as_str();
+    let interval = config.polling_rate.unwrap_or(2000);
+    let max_attempts = config.max_esl_attempts.unwrap_or(3);
+    let batch_size = config.esl_batch_size.unwrap_or(50).max(1);
+    // failed print requests waiting for the next cycle, and the attempts made for each esl id
+    let mut retries: Vec<HublotPrintRequest> = Vec::new();
+    let mut attempts: HashMap<String, u32> = HashMap::new();
     let spinner_style = ProgressStyle::with_template("{prefix:.bold.dim} {spinner} {wide_msg}")
         .unwrap()
         .tick_chars("/|\\- ");
 
+    let outbox = Outbox::open(config);
+    for entry in outbox.load() {
+        attempts.insert(entry.request.id().to_string(), entry.attempts);
+        retries.push(entry.request);
+    }
+    if !retries.is_empty() {
+        info!("Replaying {} print requests from the outbox", retries.len());
+    }
+
+    if let Err(e) = backend.warm_up().await {
+        warn!("Cannot warm up the {} backend: {}", B::NAME, e);
+    }
+
     let m = MultiProgress::new();
-    let pb = m.add(ProgressBar::new(5));
+    let pb = if config.headless.unwrap_or(false) {
+        ProgressBar::hidden()
+    } else {
+        m.add(ProgressBar::new(5))
+    };
     pb.set_style(spinner_style.clone());
     pb.set_prefix(format!("[{}/∞]", 0));
 
     loop {
-        pb.set_message("polling_broker: Getting print requests".to_string());
-        let print_requests = get_print_requests(hublot_server_url, &client, client_serial).await?;
+        if *shutdown.borrow() {
+            info!("Shutdown requested, the polling worker has stopped");
+            return Ok(());
+        }
+        // while paused nothing is fetched nor updated, the pending requests wait in `retries`
+        let paused = CONTROL.is_paused();
+        let mut fetched = if paused {
+            status!(&pb, Level::Debug, "Polling is paused");
+            Vec::new()
+        } else {
+            status!(&pb, Level::Debug, "polling_broker: Getting print requests");
+            let fetched = get_print_requests(hublot_server_url, &client, client_serial).await;
+            METRICS.record_poll(fetched.is_ok());
+            fetched?
+        };
         pb.inc(1);
-        let pricer_requests: Vec<PricerEsl> = print_requests
-            .iter()
-            .map(|esl| esl.clone().into())
-            .collect();
+        // the print requests retried from the dashboard or the admin api start over with fresh attempts
+        let injected = if paused {
+            Vec::new()
+        } else {
+            CONTROL.take_injected()
+        };
+        for request in injected {
+            // a pending Hublot request is kept over an admin one, it still has to be acknowledged
+            if request.origin == Origin::Admin
+                && retries
+                    .iter()
+                    .any(|retry| retry.id() == request.id() && retry.origin == Origin::Hublot)
+            {
+                warn!(
+                    barcode = request.id();
+                    "Ignoring the admin print request of esl {}, a print request of the Hublot server is pending",
+                    request.id()
+                );
+                continue;
+            }
+            attempts.remove(request.id());
+            if !fetched.iter().any(|fetched| fetched.id() == request.id()) {
+                fetched.push(request);
+            }
+        }
 
-        if !pricer_requests.is_empty() {
-            let ids: Vec<String> = pricer_requests
-                .iter()
-                .map(|p| p.barcode.to_string())
-                .collect();
-            info!(
-                "Found {} esls to update, ids: [{:?}]",
-                pricer_requests.len(),
-                ids
-            );
-            pb.set_message(format!("{} print request found", pricer_requests.len()));
+        if !fetched.is_empty() {
+            let ids: Vec<String> = fetched.iter().map(|p| p.id().to_string()).collect();
+            info!("Found {} esls to update, ids: [{:?}]", fetched.len(), ids);
+            status!(&pb, Level::Debug, "{} print request found", fetched.len());
+        }
+        // a journal failure should not stop the ESL updates, the request is only kept in memory
+        for request in fetched
+            .iter()
+            .filter(|request| request.origin == Origin::Hublot)
+        {
+            let attempt = attempts.get(request.id()).copied().unwrap_or(0);
+            if let Err(e) = outbox.push(request, attempt) {
+                warn!(
+                    barcode = request.id(), rid = request.rid();
+                    "Cannot journal the print request of esl {}: {}",
+                    request.id(),
+                    e
+                );
+            }
         }
+        // a freshly fetched request replaces the pending retry of the same esl
+        let retried = if paused { 0 } else { retries.len() };
+        let mut print_requests: Vec<HublotPrintRequest> = retries
+            .drain(..retried)
+            .filter(|retry| !fetched.iter().any(|request| request.id() == retry.id()))
+            .collect();
+        print_requests.extend(fetched);
 
-        for (i, pricer_esl) in pricer_requests.iter().enumerate() {
-            pb.set_message(format!(
-                "{}/{} Attempting to update an ESL",
-                i + 1,
-                pricer_requests.len()
-            ));
-            pricer_service::on_poll(
-                pricer_esl.clone(),
-                esl_server_url,
-                pricer_user.clone(),
-                pricer_password.clone(),
+        let chunks = print_requests.chunks(batch_size);
+        let chunk_count = chunks.len();
+        for (i, chunk) in chunks.enumerate() {
+            if *shutdown.borrow() {
+                let left = print_requests.len() - i * batch_size;
+                info!(
+                    "Shutdown requested, {} print requests are kept in the outbox",
+                    left
+                );
+                return Ok(());
+            }
+            status!(
                 &pb,
-            )
-            .await?;
+                Level::Info,
+                "{}/{} Attempting to update a chunk of {} ESLs",
+                i + 1,
+                chunk_count,
+                chunk.len()
+            );
+            for request in chunk {
+                ACTIVITY.start(request);
+            }
+            let outcomes = backend::on_poll(&backend, chunk, &pb).await?;
+            for (request, outcome) in chunk.iter().zip(outcomes) {
+                // errors and timed out updates are retried, a rejected update is final
+                let failure = match &outcome {
+                    Err(e) => Some(e.to_string()),
+                    Ok(report) if report.outcome == UpdateOutcome::TimedOut => Some(format!(
+                        "request {} is still {}",
+                        report.request_id, report.status
+                    )),
+                    Ok(_) => None,
+                };
+                match failure {
+                    // the esl server is paused, the request waits without losing an attempt
+                    Some(reason) if outcome.as_ref().is_err_and(BackendError::is_paused) => {
+                        debug!(
+                            barcode = request.id(), rid = request.rid();
+                            "Postponing the update of esl {}: {}",
+                            request.id(),
+                            reason
+                        );
+                        let attempt = attempts.get(request.id()).copied().unwrap_or(0);
+                        ACTIVITY.finish(
+                            request.id(),
+                            ActivityState::Postponed,
+                            attempt,
+                            Some(reason),
+                        );
+                        retries.push(request.clone());
+                        continue;
+                    }
+                    Some(reason) => {
+                        let attempt = attempts.entry(request.id().to_string()).or_insert(0);
+                        *attempt += 1;
+                        if *attempt < max_attempts {
+                            warn!(
+                                barcode = request.id(), rid = request.rid();
+                                "Cannot update esl {} (attempt {}/{}), retrying on the next cycle: {}",
+                                request.id(), attempt, max_attempts, reason
+                            );
+                            if request.origin == Origin::Hublot {
+                                if let Err(e) = outbox.push(request, *attempt) {
+                                    warn!(
+                                        barcode = request.id(), rid = request.rid();
+                                        "Cannot journal the print request of esl {}: {}",
+                                        request.id(),
+                                        e
+                                    );
+                                }
+                            }
+                            ACTIVITY.finish(
+                                request.id(),
+                                ActivityState::Retrying,
+                                *attempt,
+                                Some(reason),
+                            );
+                            retries.push(request.clone());
+                            continue;
+                        }
+                        error!(
+                            barcode = request.id(), rid = request.rid();
+                            "Cannot update esl {} after {} attempts, giving up: {}",
+                            request.id(),
+                            attempt,
+                            reason
+                        );
+                        ACTIVITY.finish(
+                            request.id(),
+                            ActivityState::Failed,
+                            *attempt,
+                            Some(reason),
+                        );
+                        attempts.remove(request.id());
+                    }
+                    None => {
+                        let attempt = attempts.remove(request.id()).unwrap_or(0) + 1;
+                        let state = match &outcome {
+                            Ok(report) if report.outcome == UpdateOutcome::Failed => {
+                                ActivityState::Rejected
+                            }
+                            _ => ActivityState::Succeeded,
+                        };
+                        ACTIVITY.finish(request.id(), state, attempt, None);
+                    }
+                }
+                METRICS.record_update(match &outcome {
+                    Ok(report) => match report.outcome {
+                        UpdateOutcome::Succeeded => "succeeded",
+                        UpdateOutcome::Failed => "failed",
+                        UpdateOutcome::TimedOut => "timed_out",
+                    },
+                    Err(_) => "error",
+                });
+                if request.origin == Origin::Admin {
+                    continue;
+                }
+                // a failed acknowledgement is only reported, it should not stop the ESL updates
+                let acknowledgement = PrintAcknowledgement::new(request, &outcome);
+                if let Err(e) =
+                    acknowledge(hublot_server_url, &client, client_serial, &acknowledgement).await
+                {
+                    warn!(
+                        barcode = request.id(), rid = request.rid();
+                        "Cannot acknowledge the print request of esl {}: {}",
+                        request.id(),
+                        e
+                    );
+                }
+                if let Err(e) = outbox.done(request.id()) {
+                    warn!(
+                        barcode = request.id(), rid = request.rid();
+                        "Cannot remove esl {} from the outbox: {}",
+                        request.id(),
+                        e
+                    );
+                }
+            }
         }
         // Divide the time we have to wait so we can animate the spinner
         let mut wait = 0;
         let time = 150;
-        pb.set_message("Waiting for a new update".to_string());
+        if !paused {
+            status!(&pb, Level::Debug, "Waiting for a new update");
+        }
         loop {
             pb.inc(1);
             sleep(Duration::from_millis(time as u64)).await;
             wait += time;
-            if wait >= interval {
+            if reload.has_changed().unwrap_or(false) {
+                info!("Stopping the polling worker to apply the reloaded configuration");
+                return Ok(());
+            }
+            // a poll forced from the dashboard ends the wait
+            if wait >= interval || *shutdown.borrow() || CONTROL.take_poll_request() {
                 break;
             }
         }
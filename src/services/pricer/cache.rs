@@ -0,0 +1,143 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use log::debug;
+
+use super::labels::PricerLinks;
+
+struct CachedLabel {
+    links: Vec<PricerLinks>,
+    fetched_at: Instant,
+}
+
+/// A cache of the label barcode to Pricer items mappings.
+///
+/// Entries are fresh for `ttl`, then kept for another `ttl` so that updates can go on
+/// with a stale mapping while the labels endpoint is unreachable.
+pub struct LabelCache {
+    ttl: Duration,
+    labels: Mutex<HashMap<String, CachedLabel>>,
+    /// Set once the whole label listing has been loaded
+    prefilled: AtomicBool,
+}
+
+impl LabelCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            labels: Mutex::new(HashMap::new()),
+            prefilled: AtomicBool::new(false),
+        }
+    }
+
+    fn lookup(&self, barcode: &str, max_age: Duration) -> Option<Vec<PricerLinks>> {
+        let labels = self.labels.lock().unwrap();
+        labels
+            .get(barcode)
+            .filter(|label| label.fetched_at.elapsed() < max_age)
+            .map(|label| label.links.clone())
+    }
+
+    /// Returns the items linked to `barcode` if they were fetched less than `ttl` ago
+    pub fn get(&self, barcode: &str) -> Option<Vec<PricerLinks>> {
+        self.lookup(barcode, self.ttl)
+    }
+
+    /// Returns the items linked to `barcode` even if they have expired, within the grace period
+    pub fn get_stale(&self, barcode: &str) -> Option<Vec<PricerLinks>> {
+        self.lookup(barcode, self.ttl.saturating_mul(2))
+    }
+
+    pub fn insert(&self, barcode: String, links: Vec<PricerLinks>) {
+        let mut labels = self.labels.lock().unwrap();
+        labels.insert(
+            barcode,
+            CachedLabel {
+                links,
+                fetched_at: Instant::now(),
+            },
+        );
+    }
+
+    pub fn is_prefilled(&self) -> bool {
+        self.prefilled.load(Ordering::Relaxed)
+    }
+
+    pub fn mark_prefilled(&self) {
+        self.prefilled.store(true, Ordering::Relaxed);
+    }
+
+    /// Drops the mapping of `barcode`, the next update will fetch it again
+    pub fn invalidate(&self, barcode: &str) {
+        debug!(barcode; "Invalidating the cached items of label {}", barcode);
+        self.labels.lock().unwrap().remove(barcode);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+
+    use super::*;
+
+    const TTL: Duration = Duration::from_millis(100);
+
+    fn links(barcode: &str) -> Vec<PricerLinks> {
+        vec![PricerLinks {
+            barcode: barcode.to_string(),
+            item_id: "1234".to_string(),
+            display_position: 0,
+        }]
+    }
+
+    #[test]
+    fn returns_a_fresh_label() {
+        let cache = LabelCache::new(TTL);
+        assert!(cache.get("0001").is_none());
+        cache.insert("0001".to_string(), links("0001"));
+        assert_eq!(cache.get("0001").unwrap()[0].item_id, "1234");
+        assert!(cache.get_stale("0001").is_some());
+    }
+
+    #[test]
+    fn keeps_an_expired_label_for_the_grace_period() {
+        let cache = LabelCache::new(TTL);
+        cache.insert("0001".to_string(), links("0001"));
+        sleep(TTL + TTL / 2);
+        assert!(cache.get("0001").is_none());
+        assert!(cache.get_stale("0001").is_some());
+        sleep(TTL);
+        assert!(cache.get_stale("0001").is_none());
+    }
+
+    #[test]
+    fn accepts_a_huge_ttl() {
+        let cache = LabelCache::new(Duration::MAX);
+        cache.insert("0001".to_string(), links("0001"));
+        assert!(cache.get("0001").is_some());
+        assert!(cache.get_stale("0001").is_some());
+    }
+
+    #[test]
+    fn invalidates_a_label() {
+        let cache = LabelCache::new(TTL);
+        cache.insert("0001".to_string(), links("0001"));
+        cache.invalidate("0001");
+        assert!(cache.get("0001").is_none());
+        assert!(cache.get_stale("0001").is_none());
+    }
+
+    #[test]
+    fn remembers_the_prefill() {
+        let cache = LabelCache::new(TTL);
+        assert!(!cache.is_prefilled());
+        cache.mark_prefilled();
+        assert!(cache.is_prefilled());
+    }
+}
@@ -1,5 +1,5 @@
 use log::debug;
-use reqwest::StatusCode;
+use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
 
 use crate::services::pricer_service::{PricerError, PricerEsl};
@@ -11,19 +11,19 @@ pub struct PricerAccepted {
     pub request_id: i32,
 }
 
-pub async fn update_item(
-    esl: PricerEsl,
+/// Updates every item of `esls` with a single request
+pub async fn update_items(
+    esls: &[PricerEsl],
     esl_server_url: &str,
+    client: &Client,
     pricer_user: String,
     pricer_password: String,
 ) -> Result<PricerAccepted, PricerError> {
-    let client = reqwest::Client::new();
     let url = format!("{esl_server_url}/api/public/core/v1/items");
-    let payload = vec![&esl];
     let response = client
         .patch(url)
         .basic_auth(pricer_user, Some(pricer_password))
-        .json(&payload)
+        .json(esls)
         .send()
         .await?;
     match response.status() {
@@ -34,7 +34,11 @@ pub async fn update_item(
         }
         _reqwest_error => {
             debug!("Esl server denied the update: {}", response.status());
-            Err(PricerError::UpdateFailed { id: esl.item_id })
+            let ids: Vec<&str> = esls.iter().map(|esl| esl.item_id.as_str()).collect();
+            Err(PricerError::UpdateFailed {
+                id: ids.join(", "),
+                status: response.status(),
+            })
         }
     }
 }
@@ -1,38 +1,35 @@
 use log::debug;
-use reqwest::StatusCode;
+use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
 
-use crate::services::pricer_service::{PricerError, PricerEsl};
+use crate::services::{esl_service::DisplayTarget, pricer_service::PricerError};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
-struct PricerLinks {
-    barcode: String,
+pub struct PricerLinks {
+    pub barcode: String,
     #[serde(rename = "itemId")]
-    item_id: String,
+    pub item_id: String,
     #[serde(rename = "displayPosition")]
-    display_position: i32,
+    pub display_position: i32,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
-struct PricerLabels {
-    barcode: String,
+pub struct PricerLabels {
+    pub barcode: String,
     #[serde(rename = "modelName")]
-    model_name: String,
-    links: Vec<PricerLinks>,
+    pub model_name: String,
+    pub links: Vec<PricerLinks>,
 }
 
-/// Returns the item_id linked to the esl_id
-pub async fn map_esl_to_id(
-    esl: PricerEsl,
+/// Returns the label registered with `barcode`, along with the items linked to it
+pub async fn get_label(
+    barcode: &str,
     esl_server_url: &str,
+    client: &Client,
     pricer_user: String,
     pricer_password: String,
-) -> Result<PricerEsl, PricerError> {
-    let client = reqwest::Client::new();
-    let url = format!(
-        "{}/api/public/core/v1/labels/{}",
-        esl_server_url, esl.barcode
-    );
+) -> Result<PricerLabels, PricerError> {
+    let url = format!("{}/api/public/core/v1/labels/{}", esl_server_url, barcode);
     let response = client
         .get(url)
         .basic_auth(pricer_user, Some(pricer_password))
@@ -40,22 +37,78 @@ pub async fn map_esl_to_id(
         .await?;
 
     match response.status() {
-        StatusCode::OK => {
-            let body: PricerLabels = response.json().await?;
-            // Default implem: use the first item linked to this barcode
-            match body.links.get(0) {
-                Some(link) => {
-                    let id = &link.item_id;
-                    let mut updated = esl.clone();
-                    updated.item_id = id.clone();
-                    Ok(updated)
+        StatusCode::OK => Ok(response.json().await?),
+        StatusCode::NOT_FOUND => {
+            debug!(barcode; "No matching items found: {}", response.status());
+            Err(PricerError::MissingItem)
+        }
+        status => {
+            debug!(barcode; "Cannot fetch the label {}: {}", barcode, status);
+            Err(PricerError::LabelUnavailable {
+                barcode: barcode.to_string(),
+                status,
+            })
+        }
+    }
+}
+
+/// Returns the links of the label `barcode` targeted by `target`
+pub fn select_links(
+    links: &[PricerLinks],
+    barcode: &str,
+    target: DisplayTarget,
+) -> Result<Vec<PricerLinks>, PricerError> {
+    let selected: Vec<PricerLinks> = match target {
+        // Default implem: use the first item linked to this barcode
+        DisplayTarget::First => links.iter().take(1).cloned().collect(),
+        DisplayTarget::Position(position) => {
+            let link = links
+                .iter()
+                .find(|link| link.display_position == position)
+                .ok_or_else(|| PricerError::MissingPosition {
+                    barcode: barcode.to_string(),
+                    position,
+                })?;
+            vec![link.clone()]
+        }
+        DisplayTarget::All => links.to_vec(),
+    };
+    if selected.is_empty() {
+        return Err(PricerError::MissingItem);
+    }
+    Ok(selected)
+}
+
+/// Returns every label known by the Pricer server, fetched `page_size` labels at a time
+pub async fn list_labels(
+    esl_server_url: &str,
+    client: &Client,
+    pricer_user: String,
+    pricer_password: String,
+    page_size: usize,
+) -> Result<Vec<PricerLabels>, PricerError> {
+    let url = format!("{esl_server_url}/api/public/core/v1/labels");
+    let mut labels = Vec::new();
+    loop {
+        let response = client
+            .get(&url)
+            .query(&[("start", labels.len()), ("limit", page_size)])
+            .basic_auth(pricer_user.clone(), Some(pricer_password.clone()))
+            .send()
+            .await?;
+        match response.status() {
+            StatusCode::OK => {
+                let page: Vec<PricerLabels> = response.json().await?;
+                let is_last = page.len() < page_size;
+                labels.extend(page);
+                if is_last {
+                    return Ok(labels);
                 }
-                None => Err(PricerError::MissingItem),
             }
-        }
-        _ => {
-            debug!("No matching items found: {}", response.status());
-            Err(PricerError::MissingItem)
+            status => {
+                debug!("Cannot list the labels: {}", status);
+                return Err(PricerError::ListingFailed { status });
+            }
         }
     }
 }
@@ -1,3 +1,4 @@
+pub mod cache;
 pub mod item;
 pub mod labels;
 pub mod status;
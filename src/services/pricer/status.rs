@@ -1,32 +1,80 @@
 use log::debug;
-use reqwest::StatusCode;
+use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
 
-use crate::services::pricer_service::PricerError;
+use crate::{
+    services::pricer_service::PricerError,
+    utils::{
+        backoff::Backoff,
+        retry::{retry, RetryPolicy},
+    },
+};
 
 use super::item::PricerAccepted;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct PricerItemResult {
     #[serde(rename = "itemId")]
-    item_id: String,
-    status: String,
-    errors: Vec<String>,
+    pub item_id: String,
+    pub status: String,
+    pub errors: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct PricerItemsResult {
-    status: String,
+    pub status: String,
     #[serde(rename = "itemResults")]
-    item_results: Vec<PricerItemResult>,
+    pub item_results: Vec<PricerItemResult>,
+}
+
+impl PricerItemResult {
+    /// Returns true when Pricer does not know the item, the label mapping is probably outdated
+    pub fn is_not_found(&self) -> bool {
+        self.status == "NOT_FOUND"
+            || self
+                .errors
+                .iter()
+                .any(|error| error.to_lowercase().contains("not found"))
+    }
+}
+
+/// The final outcome of an update request
+#[derive(Clone, Debug)]
+pub enum PricerUpdateOutcome {
+    /// Pricer reported a final status, the items may still have failed individually
+    Completed(PricerItemsResult),
+    /// The deadline expired before Pricer reported a final status
+    TimedOut(Option<PricerItemsResult>),
+}
+
+impl PricerItemsResult {
+    /// Returns false while Pricer is still processing the request
+    pub fn is_final(&self) -> bool {
+        !matches!(
+            self.status.as_str(),
+            "PENDING" | "PROCESSING" | "IN_PROGRESS" | "QUEUED"
+        )
+    }
+
+    pub fn is_successful(&self) -> bool {
+        !matches!(self.status.as_str(), "FAILED" | "ERROR")
+            && self.item_results.iter().all(|item| item.errors.is_empty())
+    }
+
+    /// Returns true when the request went through and `item` was updated without errors
+    pub fn is_successful_for(&self, item: &PricerItemResult) -> bool {
+        !matches!(self.status.as_str(), "FAILED" | "ERROR")
+            && !matches!(item.status.as_str(), "FAILED" | "ERROR")
+            && item.errors.is_empty()
+    }
 }
 
 pub async fn item_status(
     esl_server_url: &str,
+    client: &Client,
     pricer_user: String,
     pricer_password: String,
 ) -> Result<bool, PricerError> {
-    let client = reqwest::Client::new();
     let url = format!("{esl_server_url}/api/public/core/v1/items");
     let response = client
         .get(url)
@@ -36,13 +84,14 @@ pub async fn item_status(
     Ok(response.status() == StatusCode::OK)
 }
 
+/// Fetches the result of an update request, returns None while Pricer does not know the request yet
 pub async fn items_result(
-    request_status: PricerAccepted,
+    request_status: &PricerAccepted,
     esl_server_url: &str,
+    client: &Client,
     pricer_user: String,
     pricer_password: String,
-) -> Result<PricerItemsResult, PricerError> {
-    let client = reqwest::Client::new();
+) -> Result<Option<PricerItemsResult>, PricerError> {
     let url = format!(
         "{}/api/public/core/v1/items-result/{}",
         esl_server_url, request_status.request_id
@@ -55,12 +104,65 @@ pub async fn items_result(
     match response.status() {
         StatusCode::OK => {
             let body: PricerItemsResult = response.json().await?;
-            debug!("Esl server accepted our update");
-            Ok(body)
+            debug!(requestId = request_status.request_id; "Got items result: {}", body.status);
+            Ok(Some(body))
+        }
+        StatusCode::NOT_FOUND => {
+            debug!(
+                requestId = request_status.request_id;
+                "Esl server has no result yet for {}",
+                request_status.request_id
+            );
+            Ok(None)
+        }
+        status => {
+            debug!(requestId = request_status.request_id; "Esl server denied the result: {}", status);
+            Err(PricerError::ResultUnavailable {
+                id: request_status.request_id,
+                status,
+            })
+        }
+    }
+}
+
+/// Polls the result of an update request with `backoff` until Pricer reports a final status or the
+/// deadline of `backoff` expires.
+///
+/// Every poll that fails with a transient error is retried following `policy`.
+pub async fn wait_items_result(
+    request_status: &PricerAccepted,
+    esl_server_url: &str,
+    client: &Client,
+    pricer_user: String,
+    pricer_password: String,
+    policy: &RetryPolicy,
+    mut backoff: Backoff,
+) -> Result<PricerUpdateOutcome, PricerError> {
+    let mut last = None;
+    loop {
+        if let Some(result) = retry(policy, PricerError::is_retryable, || {
+            items_result(
+                request_status,
+                esl_server_url,
+                client,
+                pricer_user.clone(),
+                pricer_password.clone(),
+            )
+        })
+        .await?
+        {
+            if result.is_final() {
+                return Ok(PricerUpdateOutcome::Completed(result));
+            }
+            last = Some(result);
         }
-        _reqwest_error => {
-            debug!("Esl server denied the update: {}", response.status());
-            Err(PricerError::MissingItem)
+        if !backoff.wait().await {
+            debug!(
+                requestId = request_status.request_id;
+                "Timed out waiting for the result of {}",
+                request_status.request_id
+            );
+            return Ok(PricerUpdateOutcome::TimedOut(last));
         }
     }
 }
@@ -1,9 +1,34 @@
-use std::io;
+use std::{
+    future::Future,
+    io,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
-use super::pricer::{item::update_item, labels::map_esl_to_id, status::items_result};
+use super::backend::{BackendError, EslBackend, UpdateOutcome, UpdateReport};
+use super::build_pricer_client;
+use super::esl_service::HublotPrintRequest;
+use super::metrics::METRICS;
+use super::pricer::{
+    cache::LabelCache,
+    item::{update_items, PricerAccepted},
+    labels::{get_label, list_labels, select_links, PricerLabels},
+    status::{
+        item_status, items_result, wait_items_result, PricerItemsResult, PricerUpdateOutcome,
+    },
+};
+use crate::{
+    settings::{PricerSettings, Settings},
+    utils::{
+        backoff::Backoff,
+        circuit_breaker::{CircuitBreaker, CircuitState},
+        retry::{retry, RetryPolicy},
+    },
+};
 use esl_utils::generic_esl::GenericEsl;
-use indicatif::ProgressBar;
-use log::debug;
+use lazy_static::lazy_static;
+use log::{debug, info, warn};
+use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, NoneAsEmptyString};
 
@@ -16,8 +41,32 @@ custom_error! {
         Reqwest{source: reqwest::Error} = "An issue occured within this request: {source}",
         Io{source: io::Error}= "An I/O error occured: {source}",
         MissingItem = "Cannot find an item linked to this barcode",
-        UpdateFailed{id: String} = "PricerError, cannot update this item: {id}"
+        MissingPosition{barcode: String, position: i32} = "Label {barcode} has no item linked at displayPosition {position}",
+        LabelUnavailable{barcode: String, status: StatusCode} = "PricerError, cannot fetch the label {barcode}: {status}",
+        CircuitOpen{retry_in: u64} = "PricerError, the requests to the Pricer server are paused for {retry_in}s after repeated failures",
+        ResultUnavailable{id: i32, status: StatusCode} = "PricerError, cannot fetch the result of request {id}: {status}",
+        ListingFailed{status: StatusCode} = "PricerError, cannot list the labels: {status}",
+        UpdateFailed{id: String, status: StatusCode} = "PricerError, cannot update this item: {id} ({status})"
 }
+impl PricerError {
+    /// Transient errors (timeouts, 5xx, 429) may succeed when retried, the others would fail again
+    pub fn is_retryable(&self) -> bool {
+        let retryable = |status: StatusCode| {
+            status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+        };
+        match self {
+            PricerError::Reqwest { source } => {
+                source.is_timeout() || source.is_connect() || source.status().is_some_and(retryable)
+            }
+            PricerError::LabelUnavailable { status, .. }
+            | PricerError::ResultUnavailable { status, .. }
+            | PricerError::ListingFailed { status }
+            | PricerError::UpdateFailed { status, .. } => retryable(*status),
+            _ => false,
+        }
+    }
+}
+
 #[serde_as]
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct PricerFishProperties {
@@ -87,7 +136,7 @@ impl From<GenericEsl> for PricerEsl {
         let properties = PricerFishProperties {
             fish_name: Some(value.nom.clone()),
             fish_calibre: None,
-            /// origin = the product was not fished therefore there is no fishing gear
+            // origin = the product was not fished therefore there is no fishing gear
             fish_engin: if value.origine.is_some() {
                 None
             } else {
@@ -111,7 +160,8 @@ impl From<GenericEsl> for PricerEsl {
             promo: None,
         };
         Self {
-            item_id: value.object_id.unwrap(),
+            // the item id is resolved later on by `map_esl_to_id`
+            item_id: value.object_id.unwrap_or_default(),
             barcode: value.id,
             item_name: value.nom,
             price: None,
@@ -120,57 +170,377 @@ impl From<GenericEsl> for PricerEsl {
     }
 }
 
-// pub async fn check_status(
-//     esl: PricerEsl,
-//     esl_server_url: &str,
-//     pricer_user: String,
-//     pricer_password: String
-// )-> Result<PricerEsl, PricerError> {}
+/// A value shared by the backends built from the same settings, see `shared`
+type Shared<K, V> = Mutex<Option<(K, V)>>;
 
-pub async fn on_poll(
-    esl: PricerEsl,
-    esl_server_url: &str,
+lazy_static! {
+    /// The label cache outlives the poller, which is restarted after a crash or a configuration reload.
+    ///
+    /// It is only replaced when the Pricer server or the cache ttl change.
+    static ref LABEL_CACHE: Shared<(String, Duration), Arc<LabelCache>> = Mutex::new(None);
+    /// The http client, shared while the `[pricer]` section is unchanged
+    static ref CLIENT: Shared<PricerSettings, Client> = Mutex::new(None);
+    /// The circuit breaker, shared by every backend calling the same Pricer server
+    static ref BREAKER: Shared<(String, u32, Duration), Arc<CircuitBreaker>> = Mutex::new(None);
+}
+
+/// The state of the circuit breaker of the Pricer server, None until a Pricer backend is built
+pub fn circuit_state() -> Option<CircuitState> {
+    BREAKER
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|(_, breaker)| breaker.state())
+}
+
+/// Returns the value kept in `slot` while its `key` is unchanged, or replaces it with the one built by `build`
+fn shared<K: PartialEq, V: Clone, E>(
+    slot: &Shared<K, V>,
+    key: K,
+    build: impl FnOnce() -> Result<V, E>,
+) -> Result<V, E> {
+    let mut slot = slot.lock().unwrap();
+    match &*slot {
+        Some((current, value)) if *current == key => Ok(value.clone()),
+        _ => {
+            let value = build()?;
+            *slot = Some((key, value.clone()));
+            Ok(value)
+        }
+    }
+}
+
+/// The Pricer public core api, authenticated with basic auth
+#[derive(Clone)]
+pub struct PricerBackend {
+    esl_server_url: String,
+    /// The http client shared by every Pricer call, configured by the `[pricer]` section
+    client: Client,
     pricer_user: String,
     pricer_password: String,
-    pb: &ProgressBar,
-) -> Result<PricerEsl, PricerError> {
-    //first: We need to map the esl barcode to a pricer item_id
-    pb.inc(1);
-    pb.set_message(format!("[1/3] Getting items for esl id {}", esl.barcode));
-    let mapped_esl = map_esl_to_id(
-        esl,
-        esl_server_url,
-        pricer_user.clone(),
-        pricer_password.clone(),
-    )
-    .await?;
-
-    debug!("Got mapped ESL: {:?}", mapped_esl);
-    pb.inc(1);
-    pb.set_message(format!("[2/3] Updating item id {}", mapped_esl.item_id));
-    // then we can request pricer to update the item with the matching id
-    let update_request = update_item(
-        mapped_esl.clone(),
-        esl_server_url,
-        pricer_user.clone(),
-        pricer_password.clone(),
-    )
-    .await?;
-    debug!("Got request status: {:?}", update_request);
-    pb.inc(1);
-    pb.set_message(format!(
-        "[3/3] Checking update status for request_id {}",
-        update_request.request_id
-    ));
-    // then we can send the request id back to the api
-    let update_status = items_result(
-        update_request,
-        esl_server_url,
-        pricer_user.clone(),
-        pricer_password.clone(),
-    )
-    .await?;
-    debug!("Got update_status {:?}", update_status);
-
-    Ok(mapped_esl)
+    result_timeout: Duration,
+    labels: Arc<LabelCache>,
+    prefill_labels: bool,
+    retry_policy: RetryPolicy,
+    breaker: Arc<CircuitBreaker>,
+}
+
+impl PricerBackend {
+    /// Returns the label registered with `barcode` and the items linked to it, bypassing the cache
+    pub async fn label(&self, barcode: &str) -> Result<PricerLabels, PricerError> {
+        self.guard(
+            "map",
+            retry(&self.retry_policy, PricerError::is_retryable, || {
+                get_label(
+                    barcode,
+                    &self.esl_server_url,
+                    &self.client,
+                    self.pricer_user.clone(),
+                    self.pricer_password.clone(),
+                )
+            }),
+        )
+        .await
+    }
+
+    /// Returns the result of the update request `request_id`, None while Pricer does not know it
+    pub async fn items_result(
+        &self,
+        request_id: i32,
+    ) -> Result<Option<PricerItemsResult>, PricerError> {
+        let request = PricerAccepted { request_id };
+        self.guard(
+            "confirm",
+            retry(&self.retry_policy, PricerError::is_retryable, || {
+                items_result(
+                    &request,
+                    &self.esl_server_url,
+                    &self.client,
+                    self.pricer_user.clone(),
+                    self.pricer_password.clone(),
+                )
+            }),
+        )
+        .await
+    }
+
+    /// Runs a Pricer call unless the circuit breaker paused the requests, and feeds it with the outcome.
+    ///
+    /// The calls that reach the Pricer server are recorded in the metrics under `stage`.
+    async fn guard<T>(
+        &self,
+        stage: &'static str,
+        call: impl Future<Output = Result<T, PricerError>>,
+    ) -> Result<T, PricerError> {
+        if let Err(remaining) = self.breaker.check() {
+            return Err(PricerError::CircuitOpen {
+                retry_in: remaining.as_secs(),
+            });
+        }
+        let started = Instant::now();
+        let result = call.await;
+        METRICS.record_esl_call(stage, result.is_ok(), started.elapsed());
+        match &result {
+            Err(e) if e.is_retryable() => self.breaker.record_failure(),
+            _ => self.breaker.record_success(),
+        }
+        result
+    }
+}
+
+impl EslBackend for PricerBackend {
+    const NAME: &'static str = "pricer";
+    type Esl = PricerEsl;
+    type Ticket = PricerAccepted;
+
+    fn from_settings(config: &Settings) -> Result<Self, BackendError> {
+        let label_ttl = Duration::from_secs(config.label_cache_ttl.unwrap_or(3600));
+        let breaker_key = (
+            config.esl_server_url.clone(),
+            config.circuit_breaker_threshold.unwrap_or(5),
+            Duration::from_secs(config.circuit_breaker_cooldown.unwrap_or(60)),
+        );
+        let missing = |key: &str| BackendError::MissingKey {
+            backend: Self::NAME.to_string(),
+            key: key.to_string(),
+        };
+        Ok(Self {
+            esl_server_url: config.esl_server_url.clone(),
+            client: shared(&CLIENT, config.pricer.clone(), || {
+                build_pricer_client(&config.pricer)
+            })?,
+            pricer_user: config
+                .pricer_user
+                .clone()
+                .ok_or_else(|| missing("pricer_user"))?,
+            pricer_password: config
+                .pricer_password
+                .clone()
+                .ok_or_else(|| missing("pricer_password"))?,
+            result_timeout: Duration::from_millis(config.esl_result_timeout.unwrap_or(30000)),
+            labels: shared::<_, _, BackendError>(
+                &LABEL_CACHE,
+                (config.esl_server_url.clone(), label_ttl),
+                || Ok(Arc::new(LabelCache::new(label_ttl))),
+            )?,
+            prefill_labels: config.label_cache_prefill.unwrap_or(false),
+            retry_policy: RetryPolicy {
+                max_attempts: config.retry_max_attempts.unwrap_or(3).max(1),
+                base_delay: Duration::from_millis(config.retry_base_delay.unwrap_or(500)),
+                jitter: Duration::from_millis(config.retry_jitter.unwrap_or(250)),
+            },
+            breaker: shared::<_, _, BackendError>(&BREAKER, breaker_key.clone(), || {
+                Ok(Arc::new(CircuitBreaker::new(
+                    "The Pricer server",
+                    breaker_key.1,
+                    breaker_key.2,
+                )))
+            })?,
+        })
+    }
+
+    fn missing_keys(config: &Settings) -> Vec<&'static str> {
+        [
+            ("pricer_user", config.pricer_user.is_none()),
+            ("pricer_password", config.pricer_password.is_none()),
+        ]
+        .into_iter()
+        .filter_map(|(key, missing)| missing.then_some(key))
+        .collect()
+    }
+
+    async fn warm_up(&self) -> Result<(), BackendError> {
+        // the cache is kept across the poller restarts, it is only filled once
+        if !self.prefill_labels || self.labels.is_prefilled() {
+            return Ok(());
+        }
+        let labels = self
+            .guard(
+                "prefill",
+                retry(&self.retry_policy, PricerError::is_retryable, || {
+                    list_labels(
+                        &self.esl_server_url,
+                        &self.client,
+                        self.pricer_user.clone(),
+                        self.pricer_password.clone(),
+                        500,
+                    )
+                }),
+            )
+            .await?;
+        let count = labels.len();
+        for label in labels {
+            self.labels.insert(label.barcode, label.links);
+        }
+        self.labels.mark_prefilled();
+        info!("Prefilled the label cache with {} labels", count);
+        Ok(())
+    }
+
+    async fn map(&self, request: &HublotPrintRequest) -> Result<Vec<PricerEsl>, BackendError> {
+        let esl: PricerEsl = request.esl.clone().into();
+        let links = match self.labels.get(&esl.barcode) {
+            Some(links) => links,
+            None => match self
+                .guard(
+                    "map",
+                    retry(&self.retry_policy, PricerError::is_retryable, || {
+                        get_label(
+                            &esl.barcode,
+                            &self.esl_server_url,
+                            &self.client,
+                            self.pricer_user.clone(),
+                            self.pricer_password.clone(),
+                        )
+                    }),
+                )
+                .await
+            {
+                Ok(label) => {
+                    self.labels.insert(esl.barcode.clone(), label.links.clone());
+                    label.links
+                }
+                // the labels endpoint is unreachable, an expired mapping is better than no update
+                Err(e) if e.is_retryable() || matches!(e, PricerError::CircuitOpen { .. }) => {
+                    match self.labels.get_stale(&esl.barcode) {
+                        Some(links) => {
+                            warn!(
+                                barcode = esl.barcode.as_str();
+                                "Cannot fetch the label {}, using its cached items: {}",
+                                esl.barcode, e
+                            );
+                            links
+                        }
+                        None => return Err(e.into()),
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            },
+        };
+        Ok(select_links(&links, &esl.barcode, request.target())?
+            .into_iter()
+            .map(|link| PricerEsl {
+                item_id: link.item_id,
+                ..esl.clone()
+            })
+            .collect())
+    }
+
+    async fn update(&self, esls: &[PricerEsl]) -> Result<PricerAccepted, BackendError> {
+        let accepted = self
+            .guard(
+                "update",
+                retry(&self.retry_policy, PricerError::is_retryable, || {
+                    update_items(
+                        esls,
+                        &self.esl_server_url,
+                        &self.client,
+                        self.pricer_user.clone(),
+                        self.pricer_password.clone(),
+                    )
+                }),
+            )
+            .await;
+        if let Err(PricerError::UpdateFailed {
+            status: StatusCode::NOT_FOUND,
+            ..
+        }) = accepted
+        {
+            for esl in esls {
+                self.labels.invalidate(&esl.barcode);
+            }
+        }
+        Ok(accepted?)
+    }
+
+    async fn confirm(
+        &self,
+        ticket: PricerAccepted,
+        esls: &[PricerEsl],
+    ) -> Result<Vec<UpdateReport>, BackendError> {
+        let request_id = ticket.request_id.to_string();
+        let outcome = self
+            .guard(
+                "confirm",
+                wait_items_result(
+                    &ticket,
+                    &self.esl_server_url,
+                    &self.client,
+                    self.pricer_user.clone(),
+                    self.pricer_password.clone(),
+                    &self.retry_policy,
+                    Backoff::new(self.result_timeout),
+                ),
+            )
+            .await?;
+        let result = match outcome {
+            PricerUpdateOutcome::Completed(result) => result,
+            PricerUpdateOutcome::TimedOut(last) => {
+                let status = last.map_or("PENDING".to_string(), |result| result.status);
+                return Ok(esls
+                    .iter()
+                    .map(|_| UpdateReport {
+                        request_id: request_id.clone(),
+                        status: status.clone(),
+                        errors: Vec::new(),
+                        outcome: UpdateOutcome::TimedOut,
+                    })
+                    .collect());
+            }
+        };
+        // match the per-item results back to the esls of the request
+        Ok(esls
+            .iter()
+            .map(|esl| {
+                let item = result
+                    .item_results
+                    .iter()
+                    .find(|item| item.item_id == esl.item_id);
+                let success = match item {
+                    Some(item) => result.is_successful_for(item),
+                    None => result.is_successful(),
+                };
+                debug!(
+                    barcode = esl.barcode.as_str(),
+                    itemId = esl.item_id.as_str(),
+                    requestId = request_id.as_str();
+                    "Item {} of label {} is {}",
+                    esl.item_id,
+                    esl.barcode,
+                    item.map_or(&result.status, |item| &item.status)
+                );
+                if item.is_some_and(|item| item.is_not_found()) {
+                    self.labels.invalidate(&esl.barcode);
+                }
+                UpdateReport {
+                    request_id: request_id.clone(),
+                    status: item.map_or(result.status.clone(), |item| item.status.clone()),
+                    errors: item.map_or(Vec::new(), |item| item.errors.clone()),
+                    outcome: if success {
+                        UpdateOutcome::Succeeded
+                    } else {
+                        UpdateOutcome::Failed
+                    },
+                }
+            })
+            .collect())
+    }
+
+    async fn health(&self) -> Result<bool, BackendError> {
+        match self
+            .guard(
+                "health",
+                item_status(
+                    &self.esl_server_url,
+                    &self.client,
+                    self.pricer_user.clone(),
+                    self.pricer_password.clone(),
+                ),
+            )
+            .await
+        {
+            Err(PricerError::CircuitOpen { .. }) => Ok(false),
+            result => Ok(result?),
+        }
+    }
 }
@@ -1,9 +1,59 @@
-use config::{Config, ConfigError, File};
+use config::builder::DefaultState;
+use config::{Config, ConfigBuilder, ConfigError, Environment, File, Map, Source, Value};
 use log::info;
 use serde::Deserialize;
 
-#[derive(Debug, Deserialize, Clone)]
+pub mod reload;
+pub mod secrets;
+pub mod validation;
+
+/// The configuration file read when `--config` is not set
+pub const DEFAULT_CONFIG_FILE: &str = "hublot-config.toml";
+
+/// The per-store configuration file read, when it exists, if `--store-config` is not set
+pub const DEFAULT_STORE_CONFIG_FILE: &str = "hublot-store.toml";
+
+/// The log file used when `log_file` is not set
+pub const DEFAULT_LOG_FILE: &str = "hublot-pricer/pricer.log";
+
+/// The `[pricer]` section, used to reach the Pricer server
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+pub struct PricerSettings {
+    /// Proxy used for every request to the Pricer server
+    pub proxy_cs: Option<String>,
+    /// PEM root certificate trusted in addition to the system ones, for a self-signed Pricer server
+    pub root_certificate_path: Option<String>,
+    /// PEM client certificate presented to a Pricer server requiring mutual TLS, set with `client_key_path`
+    pub client_certificate_path: Option<String>,
+    /// PEM PKCS#8 key of `client_certificate_path`
+    pub client_key_path: Option<String>,
+    /// Skips the verification of the Pricer server certificate, only meant for testing
+    pub accept_invalid_certs: Option<bool>,
+    /// Maximum duration in ms of a request to the Pricer server
+    pub timeout: Option<u64>,
+    /// Maximum duration in ms to connect to the Pricer server
+    pub connect_timeout: Option<u64>,
+}
+
+/// The `[logging]` section, how the log file is rotated and cleaned up
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+pub struct LoggingSettings {
+    /// When the log file is rotated: "hourly", "daily" (default) or a size such as "10MB"
+    pub rotation: Option<String>,
+    /// Number of rotated files kept, instead of `max_age_days`
+    pub max_files: Option<usize>,
+    /// Age in days after which a rotated file is removed, 7 by default
+    pub max_age_days: Option<i64>,
+    /// Compresses the rotated files with gzip, except the last one
+    pub compress: Option<bool>,
+    /// chrono format of the suffix of the rotated files, e.g. "%Y-%m-%d" for a daily rotation
+    pub suffix_format: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
 pub struct Settings {
+    /// Required, left empty when missing so that `validate` reports it with the other problems
+    #[serde(default)]
     pub client_serial: String,
     pub proxy_cs: Option<String>,
     pub certificate_pem_path: Option<String>,
@@ -11,26 +61,197 @@ pub struct Settings {
     // only when using native-tls
     pub certificate_key_path: Option<String>,
     pub log_level: Option<String>,
+    #[serde(default)]
     pub esl_server_url: String,
+    #[serde(default)]
     pub hublot_server_url: String,
     pub polling_rate: Option<i32>,
+    /// Number of polling cycles a failing print request is attempted before it is reported as failed
+    pub max_esl_attempts: Option<u32>,
+    /// Maximum number of items sent to the esl server in a single update request
+    pub esl_batch_size: Option<usize>,
+    /// Maximum time in ms spent waiting for the esl server to report the result of an update
+    pub esl_result_timeout: Option<u64>,
+    /// Time in seconds a label barcode to Pricer itemId mapping is cached
+    pub label_cache_ttl: Option<u64>,
+    /// Fills the label cache with the whole Pricer label listing at startup
+    pub label_cache_prefill: Option<bool>,
+    /// Number of attempts of a Pricer call failing with a transient error (timeout, 5xx, 429)
+    pub retry_max_attempts: Option<u32>,
+    /// Delay in ms before retrying a Pricer call, doubled after every attempt
+    pub retry_base_delay: Option<u64>,
+    /// Maximum random delay in ms added to every retry
+    pub retry_jitter: Option<u64>,
+    /// Number of consecutive failed Pricer calls before the requests are paused
+    pub circuit_breaker_threshold: Option<u32>,
+    /// Time in seconds the Pricer requests are paused once the circuit breaker opened
+    pub circuit_breaker_cooldown: Option<u64>,
     pub pricer_user: Option<String>,
     pub pricer_password: Option<String>,
+    #[serde(default)]
+    pub pricer: PricerSettings,
+    /// The ESL vendor behind `esl_server_url`: "pricer" (default) or "hanshow"
+    pub esl_vendor: Option<String>,
+    /// The ESL-Working user (store code) used in the Hanshow api2 routes
+    pub hanshow_user: Option<String>,
     pub parse_id: Option<String>,
     pub parse_url: Option<String>,
     pub log_file: Option<String>,
+    /// Layout of the log file: "text" (default) or "json" for one JSON object per line
+    pub log_format: Option<String>,
+    #[serde(default)]
+    pub logging: LoggingSettings,
+    /// Address of the monitoring server serving /healthz, /readyz and /metrics, e.g. "127.0.0.1:9090", disabled when unset
+    pub monitoring_addr: Option<String>,
+    /// Port of the admin api, bound to 127.0.0.1, disabled when unset
+    pub admin_port: Option<u16>,
+    /// The bearer token required by every request to the admin api
+    pub admin_token: Option<String>,
+    /// Disables the logo, the step banners and the progress bars, set when stdout is not a terminal
+    pub headless: Option<bool>,
+    /// Shows the interactive dashboard in place of the spinner, ignored when running headless
+    pub dashboard: Option<bool>,
+    /// Directory of the print requests waiting for a final outcome, `outbox` next to the log file by default
+    pub outbox_dir: Option<String>,
+    /// The profile applied over the configuration files, selected by `--profile` or `HUBLOT_PROFILE`
+    pub profile: Option<String>,
+    /// The key file unlocking the `enc:` secrets, created by `encrypt-secret`, `hublot-secret.key` by default
+    pub secret_key_file: Option<String>,
+    /// Time in seconds the in-flight ESL updates are given to finish on SIGINT or SIGTERM, 60 by default
+    pub shutdown_timeout: Option<u64>,
 }
 
-impl Settings {
-    pub fn new() -> Result<Self, ConfigError> {
-        let config_file = "hublot-config.toml";
-        let builder = Config::builder()
-            .add_source(File::with_name(config_file))
+/// The keys of a `[profiles.<name>]` table, applied over the configuration files
+#[derive(Debug, Clone)]
+struct ProfileSource(Map<String, Value>);
+
+impl Source for ProfileSource {
+    fn clone_into_box(&self) -> Box<dyn Source + Send + Sync> {
+        Box::new(self.clone())
+    }
+
+    fn collect(&self) -> Result<Map<String, Value>, ConfigError> {
+        Ok(self.0.clone())
+    }
+}
+
+/// Where the configuration is read from, each layer overriding the previous one
+#[derive(Debug, Clone)]
+pub struct ConfigSources {
+    /// The base configuration file, shared by every store
+    pub config_file: String,
+    /// The per-store configuration file, optional unless set explicitly
+    pub store_file: Option<String>,
+    /// The `[profiles.<name>]` table to apply, overrides the `profile` key
+    pub profile: Option<String>,
+}
+
+impl ConfigSources {
+    /// Merges the configuration layers: the base file, then the per-store file, then the selected
+    /// profile and finally the `HUBLOT_*` environment variables.
+    ///
+    /// Nested keys are read from the environment with a double underscore, e.g. `HUBLOT_PRICER__TIMEOUT`.
+    fn build(&self) -> Result<Config, ConfigError> {
+        let files = || -> ConfigBuilder<DefaultState> {
+            let store_file = match &self.store_file {
+                Some(store_file) => File::with_name(store_file),
+                None => File::with_name(DEFAULT_STORE_CONFIG_FILE).required(false),
+            };
+            Config::builder()
+                .add_source(File::with_name(&self.config_file))
+                .add_source(store_file)
+        };
+        // the variables are kept as strings, e.g. a numeric client_serial keeps its leading zeros,
+        // the numeric keys are converted when the settings are deserialized
+        let environment = || {
+            Environment::with_prefix("HUBLOT")
+                .prefix_separator("_")
+                .separator("__")
+        };
+
+        // the profile may be set by any layer, its table is then applied below the environment
+        let layered = files()
+            .add_source(environment())
+            .set_override_option("profile", self.profile.clone())?
             .build()?;
+        let profile = match layered.get_string("profile") {
+            Ok(profile) => Some(profile),
+            Err(ConfigError::NotFound(_)) => None,
+            Err(e) => return Err(e),
+        };
+        let mut builder = files();
+        if let Some(profile) = &profile {
+            let table = layered.get_table(&format!("profiles.{profile}"))?;
+            builder = builder.add_source(ProfileSource(table));
+        }
+        builder
+            .add_source(environment())
+            .set_override_option("profile", profile)?
+            .build()
+    }
+
+    /// The key file unlocking the `enc:` secrets, read without resolving nor checking the other keys
+    pub fn secret_key_file(&self) -> Result<String, ConfigError> {
+        match self.build()?.get_string("secret_key_file") {
+            Ok(key_file) => Ok(key_file),
+            Err(ConfigError::NotFound(_)) => Ok(secrets::DEFAULT_SECRET_KEY_FILE.to_string()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Settings {
+    /// Reads the configuration layers of `sources` and resolves their secrets, see `ConfigSources::build`
+    pub fn new(sources: &ConfigSources) -> Result<Self, ConfigError> {
+        let mut settings: Settings = sources.build()?.try_deserialize()?;
         info!(
-            "Settings manager have built the config from the file: {}",
-            config_file
+            "Settings manager have built the config from the file: {} (profile: {:?})",
+            sources.config_file, settings.profile
         );
-        builder.try_deserialize()
+        settings
+            .resolve_secrets()
+            .map_err(|(key, e)| ConfigError::Message(format!("Cannot resolve {key}: {e}")))?;
+        Ok(settings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::*;
+
+    #[test]
+    fn reads_the_environment_over_the_files() {
+        let config_file = env::temp_dir().join(format!("hublot-{}.toml", uuid::Uuid::new_v4()));
+        fs::write(
+            &config_file,
+            "client_serial = \"store-1\"\npolling_rate = 1000\n[pricer]\ntimeout = 1000\n",
+        )
+        .unwrap();
+        env::set_var("HUBLOT_CLIENT_SERIAL", "00123");
+        env::set_var("HUBLOT_POLLING_RATE", "250");
+        env::set_var("HUBLOT_PRICER__TIMEOUT", "500");
+        env::set_var("HUBLOT_LABEL_CACHE_PREFILL", "true");
+        let settings = Settings::new(&ConfigSources {
+            config_file: config_file.to_str().unwrap().to_string(),
+            store_file: None,
+            profile: None,
+        });
+        for name in [
+            "HUBLOT_CLIENT_SERIAL",
+            "HUBLOT_POLLING_RATE",
+            "HUBLOT_PRICER__TIMEOUT",
+            "HUBLOT_LABEL_CACHE_PREFILL",
+        ] {
+            env::remove_var(name);
+        }
+        fs::remove_file(&config_file).unwrap();
+
+        let settings = settings.unwrap();
+        assert_eq!(settings.client_serial, "00123");
+        assert_eq!(settings.polling_rate, Some(250));
+        assert_eq!(settings.pricer.timeout, Some(500));
+        assert_eq!(settings.label_cache_prefill, Some(true));
     }
 }
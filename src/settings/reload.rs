@@ -0,0 +1,185 @@
+use std::{fs, time::SystemTime};
+
+use log::{error, info, warn};
+use tokio::{
+    sync::watch,
+    time::{sleep, Duration},
+};
+
+use super::{ConfigSources, Settings, DEFAULT_STORE_CONFIG_FILE};
+use crate::utils::log_filter::LogFilterHandle;
+
+/// Time between two checks of the configuration files
+const RELOAD_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Keeps the running value of the keys that are only read at startup, and lists the ones that changed
+macro_rules! keep {
+    ($current:expr, $reloaded:expr, $ignored:expr, [$($($key:ident).+),+ $(,)?]) => {
+        $(
+            if $current.$($key).+ != $reloaded.$($key).+ {
+                $reloaded.$($key).+ = $current.$($key).+.clone();
+                $ignored.push(stringify!($($key).+));
+            }
+        )+
+    };
+}
+
+impl Settings {
+    /// Applies the live keys of `reloaded` over `self`, returns the new settings and the keys
+    /// that changed but need a restart of the broker
+    pub fn reload(&self, mut reloaded: Settings) -> (Settings, Vec<&'static str>) {
+        let mut ignored = Vec::new();
+        keep!(
+            self,
+            reloaded,
+            ignored,
+            [
+                client_serial,
+                certificate_pem_path,
+                certificate_root_path,
+                certificate_key_path,
+                pricer.root_certificate_path,
+                pricer.client_certificate_path,
+                pricer.client_key_path,
+                pricer.accept_invalid_certs,
+                parse_id,
+                parse_url,
+                log_file,
+                monitoring_addr,
+                outbox_dir,
+                admin_port,
+                admin_token,
+                log_format,
+                logging,
+            ]
+        );
+        // set from the command line and the terminal by main
+        reloaded.headless = self.headless;
+        reloaded.dashboard = self.dashboard;
+        (reloaded, ignored)
+    }
+
+    /// Returns true when `reloaded` changes a key read by the polling worker, which has to be restarted.
+    ///
+    /// The log level is applied by the log filter and the shutdown timeout is read on shutdown.
+    fn restarts_poller(&self, reloaded: &Settings) -> bool {
+        let mut current = self.clone();
+        current.log_level = reloaded.log_level.clone();
+        current.shutdown_timeout = reloaded.shutdown_timeout;
+        current != *reloaded
+    }
+}
+
+/// The modification times of the configuration files of `sources`
+fn modified(sources: &ConfigSources) -> Vec<Option<SystemTime>> {
+    let store_file = sources
+        .store_file
+        .as_deref()
+        .unwrap_or(DEFAULT_STORE_CONFIG_FILE);
+    [sources.config_file.as_str(), store_file]
+        .iter()
+        .map(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok())
+        .collect()
+}
+
+/// Watches the configuration files of `sources` and publishes every valid change to `sender`.
+///
+/// The log filter is applied right away, the poller is only restarted when one of the keys it reads has changed.
+/// An invalid configuration is reported and the running one is kept.
+pub async fn watch(
+    sources: ConfigSources,
+    sender: watch::Sender<Settings>,
+    log_filter: LogFilterHandle,
+) {
+    let mut last_modified = modified(&sources);
+    loop {
+        sleep(RELOAD_INTERVAL).await;
+        let modified = modified(&sources);
+        if modified == last_modified {
+            continue;
+        }
+        last_modified = modified;
+
+        let reloaded = match Settings::new(&sources) {
+            Ok(reloaded) => reloaded,
+            Err(e) => {
+                error!(
+                    "Cannot reload the configuration, keeping the running one: {}",
+                    e
+                );
+                continue;
+            }
+        };
+        if let Err(report) = reloaded.validate() {
+            error!(
+                "Cannot reload the configuration, keeping the running one: {}",
+                report.problems.join(", ")
+            );
+            continue;
+        }
+        let current = sender.borrow().clone();
+        let (reloaded, ignored) = current.reload(reloaded);
+        for key in ignored {
+            warn!(
+                "The configuration key {} has changed, restart the broker to apply it",
+                key
+            );
+        }
+        if reloaded == current {
+            continue;
+        }
+        if reloaded.log_level != current.log_level {
+            log_filter.set(reloaded.log_level.as_deref().unwrap_or("warn"));
+        }
+        if sender.is_closed() {
+            // the poller has stopped, nothing is left to reload
+            return;
+        }
+        info!("The configuration has been reloaded");
+        // every reader sees the new settings, only the poller changes wake it up
+        let restart = current.restarts_poller(&reloaded);
+        sender.send_if_modified(|settings| {
+            *settings = reloaded;
+            restart
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use config::{Config, File, FileFormat};
+
+    use super::*;
+
+    fn settings(toml: &str) -> Settings {
+        Config::builder()
+            .add_source(File::from_str(toml, FileFormat::Toml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    #[test]
+    fn keeps_the_startup_keys() {
+        let current = settings(
+            "client_serial = \"a\"\npolling_rate = 1000\n[pricer]\nclient_key_path = \"a.key\"",
+        );
+        let (reloaded, ignored) = current.reload(settings(
+            "client_serial = \"b\"\npolling_rate = 500\n[pricer]\nclient_key_path = \"b.key\"",
+        ));
+        assert_eq!(reloaded.client_serial, "a");
+        assert_eq!(reloaded.pricer.client_key_path.as_deref(), Some("a.key"));
+        assert_eq!(reloaded.polling_rate, Some(500));
+        assert_eq!(ignored, vec!["client_serial", "pricer.client_key_path"]);
+    }
+
+    #[test]
+    fn a_log_level_change_does_not_restart_the_poller() {
+        let current = settings("log_level = \"warn\"\npolling_rate = 1000");
+        let (reloaded, _) = current.reload(settings("log_level = \"debug\"\npolling_rate = 1000"));
+        assert!(!current.restarts_poller(&reloaded));
+        let (reloaded, _) = current.reload(settings("log_level = \"debug\"\npolling_rate = 500"));
+        assert!(current.restarts_poller(&reloaded));
+    }
+}
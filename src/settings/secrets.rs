@@ -0,0 +1,255 @@
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+};
+
+use aes_gcm::{
+    aead::{Aead, KeyInit, OsRng},
+    AeadCore, Aes256Gcm, Key, Nonce,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use log::info;
+
+use super::Settings;
+
+/// The key file unlocking the `enc:` values when `secret_key_file` is not set
+pub const DEFAULT_SECRET_KEY_FILE: &str = "hublot-secret.key";
+
+/// The value shown instead of a secret
+pub const REDACTED: &str = "<redacted>";
+
+/// Length in bytes of the AES-GCM nonce stored in front of every encrypted value
+const NONCE_LENGTH: usize = 12;
+
+custom_error! {
+    /// An error that can occur while resolving a secret of the configuration.
+    ///
+    /// This error can be seamlessly converted to an `io::Error` and `base64::DecodeError` via a `From`
+    /// implementation.
+    pub SecretError
+        Io{source: io::Error} = "An I/O error occured: {source}",
+        Base64{source: base64::DecodeError} = "The encrypted value is not valid base64: {source}",
+        MissingVariable{name: String} = "The environment variable {name} is not set",
+        MissingFile{path: String, source: io::Error} = "The secret file {path} cannot be read: {source}",
+        InvalidKey{path: String} = "The key file {path} does not hold a 32 bytes key",
+        Decrypt = "The encrypted value cannot be decrypted with this machine key file",
+        Encrypt = "The value cannot be encrypted"
+}
+
+/// Creates the key file `path` with a random key, fails when it already exists.
+///
+/// On unix the file is created readable by its owner only. Windows has no such mode, the file
+/// inherits the ACL of its directory which must be restricted to the account running the broker.
+fn create_key(path: &str) -> io::Result<Key<Aes256Gcm>> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    let key = Aes256Gcm::generate_key(OsRng);
+    file.write_all(&key)?;
+    file.sync_all()?;
+    Ok(key)
+}
+
+/// Reads the machine key from `path`, creating it with a random key when `create` is set
+fn read_key(path: &str, create: bool) -> Result<Key<Aes256Gcm>, SecretError> {
+    if create {
+        match create_key(path) {
+            Ok(key) => {
+                info!("Created the secret key file {}", path);
+                return Ok(key);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    let key = fs::read(path).map_err(|source| SecretError::MissingFile {
+        path: path.to_string(),
+        source,
+    })?;
+    if key.len() != 32 {
+        return Err(SecretError::InvalidKey {
+            path: path.to_string(),
+        });
+    }
+    Ok(*Key::<Aes256Gcm>::from_slice(&key))
+}
+
+/// Encrypts `value` with the machine key of `key_file`, creating the key file when missing
+///
+/// The result is meant to be pasted as is in the configuration file, e.g. `enc:q83v...`
+pub fn encrypt(value: &str, key_file: &str) -> Result<String, SecretError> {
+    let cipher = Aes256Gcm::new(&read_key(key_file, true)?);
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let encrypted = cipher
+        .encrypt(&nonce, value.as_bytes())
+        .map_err(|_| SecretError::Encrypt)?;
+    Ok(format!(
+        "enc:{}",
+        STANDARD.encode([nonce.as_slice(), &encrypted].concat())
+    ))
+}
+
+fn decrypt(value: &str, key_file: &str) -> Result<String, SecretError> {
+    let cipher = Aes256Gcm::new(&read_key(key_file, false)?);
+    let encrypted = STANDARD.decode(value.trim())?;
+    if encrypted.len() < NONCE_LENGTH {
+        return Err(SecretError::Decrypt);
+    }
+    let (nonce, encrypted) = encrypted.split_at(NONCE_LENGTH);
+    let decrypted = cipher
+        .decrypt(Nonce::from_slice(nonce), encrypted)
+        .map_err(|_| SecretError::Decrypt)?;
+    String::from_utf8(decrypted).map_err(|_| SecretError::Decrypt)
+}
+
+/// Resolves a secret written as `env:VAR`, `file:/path` or `enc:<value>`, any other value is kept as is
+pub fn resolve(value: &str, key_file: &str) -> Result<String, SecretError> {
+    if let Some(name) = value.strip_prefix("env:") {
+        env::var(name).map_err(|_| SecretError::MissingVariable {
+            name: name.to_string(),
+        })
+    } else if let Some(path) = value.strip_prefix("file:") {
+        fs::read_to_string(path)
+            .map(|secret| secret.trim_end().to_string())
+            .map_err(|source| SecretError::MissingFile {
+                path: path.to_string(),
+                source,
+            })
+    } else if let Some(encrypted) = value.strip_prefix("enc:") {
+        decrypt(encrypted, key_file)
+    } else {
+        Ok(value.to_string())
+    }
+}
+
+impl Settings {
+    /// The key file unlocking the `enc:` values
+    pub fn secret_key_file(&self) -> &str {
+        self.secret_key_file
+            .as_deref()
+            .unwrap_or(DEFAULT_SECRET_KEY_FILE)
+    }
+
+    /// Replaces every secret written as `env:`, `file:` or `enc:` by its value
+    pub fn resolve_secrets(&mut self) -> Result<(), (&'static str, SecretError)> {
+        let key_file = self.secret_key_file().to_string();
+        let secrets = [
+            ("pricer_user", &mut self.pricer_user),
+            ("pricer_password", &mut self.pricer_password),
+            ("parse_id", &mut self.parse_id),
+            ("certificate_key_path", &mut self.certificate_key_path),
+            ("proxy_cs", &mut self.proxy_cs),
+            ("pricer.proxy_cs", &mut self.pricer.proxy_cs),
+            ("pricer.client_key_path", &mut self.pricer.client_key_path),
+            ("admin_token", &mut self.admin_token),
+        ];
+        for (key, secret) in secrets {
+            if let Some(value) = secret {
+                *value = resolve(value, &key_file).map_err(|e| (key, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// A copy of the configuration without its secrets, safe to log
+    pub fn redacted(&self) -> Settings {
+        let mut redacted = self.clone();
+        let secrets = [
+            &mut redacted.pricer_user,
+            &mut redacted.pricer_password,
+            &mut redacted.parse_id,
+            &mut redacted.certificate_key_path,
+            &mut redacted.proxy_cs,
+            &mut redacted.pricer.proxy_cs,
+            &mut redacted.pricer.client_key_path,
+            &mut redacted.admin_token,
+        ];
+        for secret in secrets.into_iter().flatten() {
+            *secret = REDACTED.to_string();
+        }
+        redacted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn key_file() -> PathBuf {
+        env::temp_dir().join(format!("hublot-secret-{}.key", uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn encrypted_values_round_trip() {
+        let key_file = key_file();
+        let key_path = key_file.to_str().unwrap();
+        let encrypted = encrypt("s3cret", key_path).unwrap();
+        assert!(encrypted.starts_with("enc:"));
+        assert_ne!(encrypted, encrypt("s3cret", key_path).unwrap());
+        assert_eq!(resolve(&encrypted, key_path).unwrap(), "s3cret");
+        fs::remove_file(&key_file).unwrap();
+    }
+
+    #[test]
+    fn another_key_cannot_decrypt() {
+        let (first, second) = (key_file(), key_file());
+        let encrypted = encrypt("s3cret", first.to_str().unwrap()).unwrap();
+        encrypt("other", second.to_str().unwrap()).unwrap();
+        assert!(matches!(
+            resolve(&encrypted, second.to_str().unwrap()),
+            Err(SecretError::Decrypt)
+        ));
+        fs::remove_file(&first).unwrap();
+        fs::remove_file(&second).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn the_key_file_is_only_readable_by_its_owner() {
+        use std::os::unix::fs::PermissionsExt;
+        let key_file = key_file();
+        encrypt("s3cret", key_file.to_str().unwrap()).unwrap();
+        let mode = fs::metadata(&key_file).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        fs::remove_file(&key_file).unwrap();
+    }
+
+    #[test]
+    fn resolves_env_and_file_secrets() {
+        env::set_var("HUBLOT_TEST_SECRET", "from-env");
+        assert_eq!(
+            resolve("env:HUBLOT_TEST_SECRET", "unused").unwrap(),
+            "from-env"
+        );
+        assert!(matches!(
+            resolve("env:HUBLOT_TEST_MISSING", "unused"),
+            Err(SecretError::MissingVariable { .. })
+        ));
+
+        let secret_file = key_file();
+        fs::write(&secret_file, "from-file\n").unwrap();
+        let value = format!("file:{}", secret_file.to_str().unwrap());
+        assert_eq!(resolve(&value, "unused").unwrap(), "from-file");
+        fs::remove_file(&secret_file).unwrap();
+
+        assert_eq!(resolve("plain", "unused").unwrap(), "plain");
+    }
+
+    #[test]
+    fn a_missing_key_file_is_not_created_to_decrypt() {
+        let key_file = key_file();
+        assert!(matches!(
+            resolve("enc:AAAA", key_file.to_str().unwrap()),
+            Err(SecretError::MissingFile { .. })
+        ));
+        assert!(!key_file.exists());
+    }
+}
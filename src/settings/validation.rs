@@ -0,0 +1,318 @@
+use std::{fmt, fs::File, str::FromStr};
+
+use chrono::format::{Item, StrftimeItems};
+
+use log::LevelFilter;
+use reqwest::{Proxy, Url};
+
+use super::Settings;
+use crate::{services::backend, utils::log_file::Rotation};
+
+/// Every problem found in the configuration
+#[derive(Debug, Clone)]
+pub struct ConfigReport {
+    pub problems: Vec<String>,
+}
+
+impl fmt::Display for ConfigReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "The configuration has {} problem(s), please fix them in the configuration file:",
+            self.problems.len()
+        )?;
+        for problem in &self.problems {
+            writeln!(f, "  - {problem}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns true when `directive` is a filter env_logger understands, e.g. `warn` or `my_crate=debug`
+fn is_log_directive(directive: &str) -> bool {
+    // a bare word that is not a level would be taken as a module name, most likely a typo
+    let level = match directive.split_once('=') {
+        Some((module, level)) => !module.is_empty() && level.parse::<LevelFilter>().is_ok(),
+        None => directive.parse::<LevelFilter>().is_ok(),
+    };
+    directive.is_empty() || level
+}
+
+impl Settings {
+    /// Checks the whole configuration, and reports every problem at once
+    pub fn validate(&self) -> Result<(), ConfigReport> {
+        let mut problems = Vec::new();
+
+        let required = [
+            ("client_serial", !self.client_serial.is_empty()),
+            ("esl_server_url", !self.esl_server_url.is_empty()),
+            ("hublot_server_url", !self.hublot_server_url.is_empty()),
+            ("parse_id", self.parse_id.is_some()),
+            ("parse_url", self.parse_url.is_some()),
+        ];
+        for (key, _) in required.into_iter().filter(|(_, present)| !present) {
+            problems.push(format!("the key '{key}' is missing"));
+        }
+        // the keys of the selected vendor are listed by its backend
+        match backend::missing_keys(self) {
+            Ok(missing) => {
+                for key in missing {
+                    problems.push(format!("the key '{key}' is missing"));
+                }
+            }
+            Err(e) => problems.push(e.to_string()),
+        }
+
+        let urls = [
+            (
+                "hublot_server_url",
+                Some(&self.hublot_server_url).filter(|url| !url.is_empty()),
+            ),
+            (
+                "esl_server_url",
+                Some(&self.esl_server_url).filter(|url| !url.is_empty()),
+            ),
+            ("parse_url", self.parse_url.as_ref()),
+        ];
+        for (key, url) in urls {
+            if let Some(Err(e)) =
+                url.map(|url| Url::parse(url).map_err(|e| format!("'{url}': {e}")))
+            {
+                problems.push(format!("{key} is not a valid url {e}"));
+            }
+        }
+
+        let proxies = [
+            ("proxy_cs", self.proxy_cs.as_ref()),
+            ("pricer.proxy_cs", self.pricer.proxy_cs.as_ref()),
+        ];
+        for (key, proxy) in proxies {
+            if let Some(Err(e)) = proxy.map(Proxy::all) {
+                problems.push(format!("{key} is not a valid proxy: {e}"));
+            }
+        }
+
+        let certificates = [
+            ("certificate_pem_path", self.certificate_pem_path.as_ref()),
+            ("certificate_root_path", self.certificate_root_path.as_ref()),
+            ("certificate_key_path", self.certificate_key_path.as_ref()),
+            (
+                "pricer.root_certificate_path",
+                self.pricer.root_certificate_path.as_ref(),
+            ),
+            (
+                "pricer.client_certificate_path",
+                self.pricer.client_certificate_path.as_ref(),
+            ),
+            (
+                "pricer.client_key_path",
+                self.pricer.client_key_path.as_ref(),
+            ),
+        ];
+        for (key, path) in certificates {
+            if let Some(Err(e)) =
+                path.map(|path| File::open(path).map_err(|e| format!("'{path}': {e}")))
+            {
+                problems.push(format!("{key} cannot be read {e}"));
+            }
+        }
+        let client_certificate = [
+            &self.certificate_pem_path,
+            &self.certificate_root_path,
+            &self.certificate_key_path,
+        ];
+        let set = client_certificate
+            .iter()
+            .filter(|path| path.is_some())
+            .count();
+        if set != 0 && set != client_certificate.len() {
+            problems.push(
+                "certificate_pem_path, certificate_root_path and certificate_key_path must be set together"
+                    .to_string(),
+            );
+        }
+
+        if self.pricer.client_certificate_path.is_some() != self.pricer.client_key_path.is_some() {
+            problems.push(
+                "pricer.client_certificate_path and pricer.client_key_path must be set together"
+                    .to_string(),
+            );
+        }
+
+        if self.admin_port.is_some() && self.admin_token.as_deref().unwrap_or_default().is_empty() {
+            problems.push("admin_port requires an admin_token".to_string());
+        }
+
+        if let Some(rate) = self.polling_rate.filter(|rate| *rate <= 0) {
+            problems.push(format!(
+                "polling_rate must be a positive number of ms, got {rate}"
+            ));
+        }
+        if let Some(level) = &self.log_level {
+            let filter = level.split('/').next().unwrap_or_default();
+            if !filter.split(',').map(str::trim).all(is_log_directive) {
+                problems.push(format!(
+                    "log_level '{level}' is not valid, expected a level such as 'warn' or 'esl_services_backend=debug'"
+                ));
+            }
+        }
+
+        if let Some(format) = self
+            .log_format
+            .as_deref()
+            .filter(|format| !matches!(*format, "text" | "json"))
+        {
+            problems.push(format!(
+                "log_format '{format}' is unknown, expected 'text' or 'json'"
+            ));
+        }
+
+        if let Some(Err(e)) = self.logging.rotation.as_deref().map(Rotation::from_str) {
+            problems.push(format!("logging.rotation is not valid: {e}"));
+        }
+        if self.logging.max_files == Some(0) {
+            problems.push("logging.max_files must keep at least 1 file".to_string());
+        }
+        if let Some(days) = self.logging.max_age_days.filter(|days| *days <= 0) {
+            problems.push(format!(
+                "logging.max_age_days must be a positive number of days, got {days}"
+            ));
+        }
+        if self.logging.max_files.is_some() && self.logging.max_age_days.is_some() {
+            problems.push(
+                "logging.max_files and logging.max_age_days cannot be set together".to_string(),
+            );
+        }
+        if let Some(format) = &self.logging.suffix_format {
+            let items: Vec<Item> = StrftimeItems::new(format).collect();
+            let rotation = match self.logging.rotation.as_deref().map(Rotation::from_str) {
+                Some(Ok(rotation)) => Some(rotation),
+                Some(Err(_)) => None,
+                None => Some(Rotation::Daily),
+            };
+            if items.is_empty() || items.contains(&Item::Error) {
+                problems.push(format!(
+                    "logging.suffix_format '{format}' is not a valid date format"
+                ));
+            } else if let Some(rotation) = rotation.filter(|rotation| !rotation.reads_back(format))
+            {
+                problems.push(format!(
+                    "logging.suffix_format '{format}' does not hold the full date of a {rotation:?} rotation, the rotated files would collide and never be removed"
+                ));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigReport { problems })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use config::{Config, File, FileFormat};
+
+    use super::*;
+
+    const VALID: &str = r#"
+        client_serial = "store-1"
+        esl_server_url = "http://pricer.local"
+        hublot_server_url = "https://hublot.local"
+        parse_id = "id"
+        parse_url = "https://parse.local"
+        pricer_user = "user"
+        pricer_password = "password"
+    "#;
+
+    fn settings(toml: &str) -> Settings {
+        Config::builder()
+            .add_source(File::from_str(toml, FileFormat::Toml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    fn problems(toml: &str) -> Vec<String> {
+        settings(toml)
+            .validate()
+            .err()
+            .map_or(Vec::new(), |report| report.problems)
+    }
+
+    #[test]
+    fn accepts_a_valid_configuration() {
+        assert_eq!(problems(VALID), Vec::<String>::new());
+    }
+
+    #[test]
+    fn reports_the_missing_required_keys() {
+        let problems = problems(r#"pricer_user = "user""#);
+        for key in [
+            "client_serial",
+            "esl_server_url",
+            "hublot_server_url",
+            "parse_id",
+            "parse_url",
+            "pricer_password",
+        ] {
+            assert!(
+                problems.contains(&format!("the key '{key}' is missing")),
+                "{key} is not reported in {problems:?}"
+            );
+        }
+        assert_eq!(problems.len(), 6);
+    }
+
+    #[test]
+    fn reports_the_keys_of_the_selected_vendor() {
+        let problems = problems(&format!("{VALID}\nesl_vendor = \"hanshow\""));
+        assert_eq!(problems, vec!["the key 'hanshow_user' is missing"]);
+    }
+
+    #[test]
+    fn reports_an_unknown_vendor() {
+        let problems = problems(&format!("{VALID}\nesl_vendor = \"other\""));
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("'other'"));
+    }
+
+    #[test]
+    fn reports_every_problem_at_once() {
+        let problems = problems(&format!(
+            "{VALID}\npolling_rate = 0\nlog_format = \"xml\"\nadmin_port = 8799"
+        ));
+        assert_eq!(problems.len(), 3, "{problems:?}");
+    }
+
+    #[test]
+    fn reports_the_invalid_urls() {
+        let problems = problems(&VALID.replace("http://pricer.local", "pricer.local"));
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("esl_server_url is not a valid url"));
+    }
+
+    #[test]
+    fn reports_a_suffix_format_without_the_full_date() {
+        let reported = problems(&format!("{VALID}\n[logging]\nsuffix_format = \"%Y-%m\""));
+        assert_eq!(reported.len(), 1, "{reported:?}");
+        assert!(reported[0].contains("does not hold the full date"));
+        let reported = problems(&format!(
+            "{VALID}\n[logging]\nrotation = \"hourly\"\nsuffix_format = \"%Y%m%dT%H\""
+        ));
+        assert_eq!(reported, Vec::<String>::new());
+    }
+
+    #[test]
+    fn reports_the_invalid_log_rotations() {
+        let problems = problems(&format!(
+            "{VALID}\n[logging]\nrotation = \"{}GB\"\nmax_files = 3\nmax_age_days = 7",
+            usize::MAX
+        ));
+        assert_eq!(problems.len(), 2, "{problems:?}");
+        assert!(problems[0].contains("too large"));
+        assert!(problems[1].contains("cannot be set together"));
+    }
+}
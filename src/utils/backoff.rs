@@ -0,0 +1,60 @@
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+
+/// Delay before the second attempt, doubled after every attempt
+const INITIAL_DELAY: Duration = Duration::from_millis(250);
+/// Upper bound of the delay between two attempts
+const MAX_DELAY: Duration = Duration::from_secs(5);
+
+/// An exponential backoff bounded by a deadline
+pub struct Backoff {
+    deadline: Instant,
+    delay: Duration,
+}
+
+impl Backoff {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            deadline: Instant::now() + timeout,
+            delay: INITIAL_DELAY,
+        }
+    }
+
+    /// Waits before the next attempt, returns false once the deadline has been reached
+    pub async fn wait(&mut self) -> bool {
+        let now = Instant::now();
+        if now >= self.deadline {
+            return false;
+        }
+        sleep(self.delay.min(self.deadline - now)).await;
+        self.delay = (self.delay * 2).min(MAX_DELAY);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn doubles_the_delay_up_to_the_deadline() {
+        let started = Instant::now();
+        let mut backoff = Backoff::new(Duration::from_millis(600));
+        assert!(backoff.wait().await);
+        assert_eq!(backoff.delay, INITIAL_DELAY * 2);
+        // the second wait is cut short by the deadline
+        assert!(backoff.wait().await);
+        assert!(!backoff.wait().await);
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(600) && elapsed < Duration::from_millis(750));
+    }
+
+    #[tokio::test]
+    async fn caps_the_delay() {
+        let mut backoff = Backoff::new(Duration::from_secs(60));
+        backoff.delay = MAX_DELAY;
+        backoff.deadline = Instant::now() + Duration::from_millis(50);
+        assert!(backoff.wait().await);
+        assert_eq!(backoff.delay, MAX_DELAY);
+    }
+}
@@ -0,0 +1,159 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use log::{info, warn};
+use serde::Serialize;
+
+#[derive(Default)]
+struct BreakerState {
+    failures: u32,
+    open_until: Option<Instant>,
+    /// Set while the trial call of a half-open circuit is running, it expires after a cooldown
+    /// so that a dropped trial cannot keep the circuit closed to every call
+    trial_until: Option<Instant>,
+}
+
+/// Where a circuit breaker stands, see `CircuitBreaker::state`
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum CircuitState {
+    /// The calls go through
+    Closed,
+    /// The calls are paused until the cooldown has expired
+    Open,
+    /// The cooldown has expired, the next call is a trial
+    HalfOpen,
+}
+
+/// Stops the calls to a server after `threshold` consecutive failures, for `cooldown`.
+///
+/// Once the cooldown has expired the circuit is half-open: a single trial call goes through while
+/// the others are still paused, a success closes the circuit and a failure opens it for another cooldown.
+pub struct CircuitBreaker {
+    name: &'static str,
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    pub fn new(name: &'static str, threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            name,
+            threshold: threshold.max(1),
+            cooldown,
+            state: Mutex::new(BreakerState::default()),
+        }
+    }
+
+    /// Returns the remaining pause while the circuit is open, or while the trial call of a
+    /// half-open circuit is running
+    pub fn check(&self) -> Result<(), Duration> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        match (state.open_until, state.trial_until) {
+            (Some(open_until), _) if open_until > now => Err(open_until - now),
+            (Some(_), Some(trial_until)) if trial_until > now => Err(trial_until - now),
+            (Some(_), _) => {
+                state.trial_until = Some(now + self.cooldown);
+                Ok(())
+            }
+            (None, _) => Ok(()),
+        }
+    }
+
+    pub fn state(&self) -> CircuitState {
+        let state = self.state.lock().unwrap();
+        match state.open_until {
+            None => CircuitState::Closed,
+            Some(open_until) if open_until > Instant::now() => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.open_until.is_some() {
+            info!("{} is reachable again, resuming the requests", self.name);
+        }
+        *state = BreakerState::default();
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.failures += 1;
+        if state.failures < self.threshold {
+            return;
+        }
+        // only the first opening is logged, the failed trials just extend the pause
+        if state.open_until.is_none() {
+            warn!(
+                "{} failed {} times in a row, pausing the requests for {:?}",
+                self.name, state.failures, self.cooldown
+            );
+        }
+        state.open_until = Some(Instant::now() + self.cooldown);
+        state.trial_until = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opens_after_threshold_failures() {
+        let breaker = CircuitBreaker::new("test", 2, Duration::from_secs(60));
+        breaker.record_failure();
+        assert!(breaker.check().is_ok());
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        breaker.record_failure();
+        assert!(breaker.check().is_err());
+        assert_eq!(breaker.state(), CircuitState::Open);
+    }
+
+    #[test]
+    fn a_success_resets_the_failures() {
+        let breaker = CircuitBreaker::new("test", 2, Duration::from_secs(60));
+        breaker.record_failure();
+        breaker.record_success();
+        breaker.record_failure();
+        assert!(breaker.check().is_ok());
+    }
+
+    #[test]
+    fn lets_a_single_trial_through_once_half_open() {
+        let breaker = CircuitBreaker::new("test", 1, Duration::from_millis(20));
+        breaker.record_failure();
+        assert!(breaker.check().is_err());
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(breaker.check().is_ok());
+        assert!(breaker.check().is_err());
+        breaker.record_success();
+        assert!(breaker.check().is_ok());
+        assert!(breaker.check().is_ok());
+    }
+
+    #[test]
+    fn a_failed_trial_opens_the_circuit_again() {
+        let breaker = CircuitBreaker::new("test", 1, Duration::from_millis(20));
+        breaker.record_failure();
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(breaker.check().is_ok());
+        breaker.record_failure();
+        assert!(breaker.check().is_err());
+    }
+
+    #[test]
+    fn a_dropped_trial_expires_after_the_cooldown() {
+        let breaker = CircuitBreaker::new("test", 1, Duration::from_millis(20));
+        breaker.record_failure();
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(breaker.check().is_ok());
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(breaker.check().is_ok());
+    }
+}
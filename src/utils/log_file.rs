@@ -0,0 +1,216 @@
+use std::{fmt::Write as _, io::Write, str::FromStr};
+
+use chrono::{
+    format::{self, Parsed, StrftimeItems},
+    NaiveDate, NaiveDateTime, Timelike,
+};
+
+use file_rotate::{
+    compression::Compression,
+    suffix::{AppendTimestamp, DateFrom, FileLimit},
+    ContentLimit, FileRotate, TimeFrequency,
+};
+
+use crate::settings::{Settings, DEFAULT_LOG_FILE};
+
+/// When the log file is rotated, from the `logging.rotation` key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    Hourly,
+    Daily,
+    /// Once the file exceeds this many bytes
+    Size(usize),
+}
+
+impl FromStr for Rotation {
+    type Err = String;
+
+    /// Reads "hourly", "daily" or a size such as "10MB", "500KB" or "1048576"
+    fn from_str(rotation: &str) -> Result<Self, Self::Err> {
+        let rotation = rotation.trim().to_ascii_lowercase();
+        match rotation.as_str() {
+            "hourly" => return Ok(Rotation::Hourly),
+            "daily" => return Ok(Rotation::Daily),
+            _ => {}
+        }
+        let (number, unit) = rotation.split_at(
+            rotation
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rotation.len()),
+        );
+        let multiplier = match unit.trim() {
+            "" | "b" => 1,
+            "kb" => 1024,
+            "mb" => 1024 * 1024,
+            "gb" => 1024 * 1024 * 1024,
+            _ => return Err(format!("unknown size unit '{}'", unit.trim())),
+        };
+        match number.parse::<usize>() {
+            Ok(size) if size > 0 => size
+                .checked_mul(multiplier)
+                .map(Rotation::Size)
+                .ok_or_else(|| format!("the size '{rotation}' is too large")),
+            _ => Err("expected 'hourly', 'daily' or a size such as '10MB'".to_string()),
+        }
+    }
+}
+
+impl Rotation {
+    /// A suffix that sorts in the rotation order and cannot collide between two rotations
+    fn default_suffix(&self) -> &'static str {
+        match self {
+            Rotation::Hourly => "%Y-%m-%dT%H",
+            Rotation::Daily => "%Y-%m-%d",
+            Rotation::Size(_) => "%Y-%m-%dT%H-%M-%S",
+        }
+    }
+
+    /// Returns true when a suffix written with `format` reads back as the rotation period it holds.
+    ///
+    /// Otherwise two periods share a suffix, or the rotated files cannot be dated by `max_age_days`.
+    pub fn reads_back(&self, format: &str) -> bool {
+        // the day is above 12 so that a swapped day and month is noticed
+        let Some(at) =
+            NaiveDate::from_ymd_opt(2024, 11, 23).and_then(|date| date.and_hms_opt(17, 42, 53))
+        else {
+            return false;
+        };
+        let mut suffix = String::new();
+        if write!(suffix, "{}", at.format(format)).is_err() {
+            return false;
+        }
+        let mut parsed = Parsed::new();
+        if format::parse(&mut parsed, &suffix, StrftimeItems::new(format)).is_err() {
+            return false;
+        }
+        let Ok(date) = parsed.to_naive_date() else {
+            return false;
+        };
+        let hour = match (parsed.hour_div_12, parsed.hour_mod_12) {
+            (Some(div), Some(rem)) => div * 12 + rem,
+            _ => 0,
+        };
+        let read = date.and_hms_opt(hour, parsed.minute.unwrap_or(0), parsed.second.unwrap_or(0));
+        read.map(|read| self.period(read)) == Some(self.period(at))
+    }
+
+    /// The start of the rotation period holding `at`
+    fn period(&self, at: NaiveDateTime) -> NaiveDateTime {
+        let (hour, minute, second) = match self {
+            Rotation::Hourly => (at.hour(), 0, 0),
+            Rotation::Daily => (0, 0, 0),
+            Rotation::Size(_) => (at.hour(), at.minute(), at.second()),
+        };
+        at.date().and_hms_opt(hour, minute, second).unwrap_or(at)
+    }
+
+    /// The suffix of a rotated file is the period it holds, not the time it was rotated
+    fn date_from(&self) -> DateFrom {
+        match self {
+            Rotation::Hourly => DateFrom::DateHourAgo,
+            Rotation::Daily => DateFrom::DateYesterday,
+            Rotation::Size(_) => DateFrom::Now,
+        }
+    }
+}
+
+/// Opens the log file of `config`, rotated and cleaned up following its `[logging]` section
+pub fn open(config: &Settings) -> Box<dyn Write + Send> {
+    let logging = &config.logging;
+    // checked by Settings::validate
+    let rotation = logging
+        .rotation
+        .as_deref()
+        .and_then(|rotation| rotation.parse().ok())
+        .unwrap_or(Rotation::Daily);
+    let file_limit = match (logging.max_files, logging.max_age_days) {
+        (Some(max_files), _) => FileLimit::MaxFiles(max_files),
+        (None, Some(days)) => FileLimit::Age(chrono::Duration::days(days)),
+        (None, None) => FileLimit::Age(chrono::Duration::days(7)),
+    };
+    // the suffix format outlives the logger, it is only read once at startup
+    let suffix_format: &'static str = match &logging.suffix_format {
+        Some(format) => Box::leak(format.clone().into_boxed_str()),
+        None => rotation.default_suffix(),
+    };
+    let content_limit = match rotation {
+        Rotation::Hourly => ContentLimit::Time(TimeFrequency::Hourly),
+        Rotation::Daily => ContentLimit::Time(TimeFrequency::Daily),
+        Rotation::Size(size) => ContentLimit::BytesSurpassed(size),
+    };
+    let compression = if logging.compress.unwrap_or(false) {
+        // the last rotated file is kept readable
+        Compression::OnRotate(1)
+    } else {
+        Compression::None
+    };
+
+    Box::new(FileRotate::new(
+        config.log_file.as_deref().unwrap_or(DEFAULT_LOG_FILE),
+        AppendTimestamp::with_format(suffix_format, file_limit, rotation.date_from()),
+        content_limit,
+        compression,
+        #[cfg(unix)]
+        None,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_time_rotations() {
+        assert_eq!("hourly".parse(), Ok(Rotation::Hourly));
+        assert_eq!(" Daily ".parse(), Ok(Rotation::Daily));
+    }
+
+    #[test]
+    fn reads_the_sizes() {
+        assert_eq!("1048576".parse(), Ok(Rotation::Size(1048576)));
+        assert_eq!("512b".parse(), Ok(Rotation::Size(512)));
+        assert_eq!("500KB".parse(), Ok(Rotation::Size(500 * 1024)));
+        assert_eq!("10 MB".parse(), Ok(Rotation::Size(10 * 1024 * 1024)));
+        assert_eq!("1gb".parse(), Ok(Rotation::Size(1024 * 1024 * 1024)));
+    }
+
+    #[test]
+    fn rejects_the_invalid_rotations() {
+        for rotation in ["", "weekly", "0MB", "10TB", "MB", "-1"] {
+            assert!(
+                rotation.parse::<Rotation>().is_err(),
+                "'{rotation}' is accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn the_default_suffixes_read_back() {
+        for rotation in [Rotation::Hourly, Rotation::Daily, Rotation::Size(1024)] {
+            assert!(
+                rotation.reads_back(rotation.default_suffix()),
+                "{rotation:?}"
+            );
+        }
+        assert!(Rotation::Daily.reads_back("%d-%m-%Y"));
+        assert!(Rotation::Daily.reads_back("%Y%m%dT%H"));
+    }
+
+    #[test]
+    fn a_suffix_must_hold_the_rotation_period() {
+        assert!(!Rotation::Daily.reads_back("%Y-%m"));
+        assert!(!Rotation::Daily.reads_back("%m-%d"));
+        assert!(!Rotation::Hourly.reads_back("%Y-%m-%d"));
+        assert!(!Rotation::Size(1024).reads_back("%Y-%m-%dT%H"));
+        assert!(!Rotation::Daily.reads_back("%Q"));
+    }
+
+    #[test]
+    fn rejects_a_size_that_overflows() {
+        let rotation = format!("{}GB", usize::MAX);
+        assert!(rotation
+            .parse::<Rotation>()
+            .unwrap_err()
+            .contains("too large"));
+    }
+}
@@ -0,0 +1,60 @@
+use std::{
+    env,
+    sync::{Arc, RwLock},
+};
+
+use env_logger::filter::{Builder, Filter};
+use log::{Log, Metadata, Record, SetLoggerError};
+
+/// Builds the filter of `log_level`, `RUST_LOG` takes precedence when set
+fn build_filter(log_level: &str) -> Filter {
+    let spec = env::var("RUST_LOG").unwrap_or_else(|_| log_level.to_string());
+    Builder::new().parse(&spec).build()
+}
+
+/// Replaces the filter of the logger installed by `init`
+#[derive(Clone)]
+pub struct LogFilterHandle(Arc<RwLock<Filter>>);
+
+impl LogFilterHandle {
+    pub fn set(&self, log_level: &str) {
+        let filter = build_filter(log_level);
+        log::set_max_level(filter.filter());
+        *self.0.write().unwrap() = filter;
+    }
+}
+
+/// A logger whose filter can be changed while the broker runs
+struct ReloadableLogger {
+    inner: env_logger::Logger,
+    filter: Arc<RwLock<Filter>>,
+}
+
+impl Log for ReloadableLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.read().unwrap().enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.filter.read().unwrap().matches(record) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Installs `inner` as the global logger, filtered by `log_level` instead of its own filter
+pub fn init(inner: env_logger::Logger, log_level: &str) -> Result<LogFilterHandle, SetLoggerError> {
+    let filter = build_filter(log_level);
+    let max_level = filter.filter();
+    let filter = Arc::new(RwLock::new(filter));
+    log::set_boxed_logger(Box::new(ReloadableLogger {
+        inner,
+        filter: filter.clone(),
+    }))?;
+    log::set_max_level(max_level);
+    Ok(LogFilterHandle(filter))
+}
@@ -0,0 +1,149 @@
+use log::{
+    kv::{Error, Key, Value, VisitSource, VisitValue},
+    Record,
+};
+use serde_json::{json, Map};
+
+/// Converts a key-value to JSON, a null value is left out
+struct JsonValue(Option<serde_json::Value>);
+
+impl<'v> VisitValue<'v> for JsonValue {
+    fn visit_any(&mut self, value: Value) -> Result<(), Error> {
+        self.0 = Some(json!(value.to_string()));
+        Ok(())
+    }
+
+    fn visit_null(&mut self) -> Result<(), Error> {
+        self.0 = None;
+        Ok(())
+    }
+
+    fn visit_u64(&mut self, value: u64) -> Result<(), Error> {
+        self.0 = Some(json!(value));
+        Ok(())
+    }
+
+    fn visit_i64(&mut self, value: i64) -> Result<(), Error> {
+        self.0 = Some(json!(value));
+        Ok(())
+    }
+
+    fn visit_bool(&mut self, value: bool) -> Result<(), Error> {
+        self.0 = Some(json!(value));
+        Ok(())
+    }
+}
+
+/// Collects the key-values of a record, e.g. `barcode`, `itemId` or `requestId`
+struct Context(Map<String, serde_json::Value>);
+
+impl<'kvs> VisitSource<'kvs> for Context {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), Error> {
+        let mut json = JsonValue(None);
+        value.visit(&mut json)?;
+        if let Some(value) = json.0 {
+            self.0.insert(key.to_string(), value);
+        }
+        Ok(())
+    }
+}
+
+/// The ESL context attached to `record`, such as the barcode, the Pricer itemId or requestId
+pub fn context(record: &Record) -> Map<String, serde_json::Value> {
+    let mut context = Context(Map::new());
+    // the visitor never fails
+    let _ = record.key_values().visit(&mut context);
+    context.0
+}
+
+/// The keys of a JSON log line, the context of a record cannot override them
+const RESERVED_KEYS: [&str; 6] = [
+    "timestamp",
+    "level",
+    "module",
+    "line",
+    "message",
+    "client_serial",
+];
+
+/// Formats `record` as a single line JSON object for `log_format = "json"`, `timestamp` being RFC 3339
+pub fn json_line(
+    record: &Record,
+    timestamp: &str,
+    client_serial: &str,
+    context: &Map<String, serde_json::Value>,
+) -> String {
+    let mut line = json!({
+        "timestamp": timestamp,
+        "level": record.level().to_string(),
+        "module": record.module_path().unwrap_or("unknown"),
+        "line": record.line().unwrap_or(0),
+        "message": record.args().to_string(),
+        "client_serial": client_serial,
+    });
+    if let Some(line) = line.as_object_mut() {
+        line.extend(
+            context
+                .iter()
+                .filter(|(key, _)| !RESERVED_KEYS.contains(&key.as_str()))
+                .map(|(key, value)| (key.clone(), value.clone())),
+        );
+    }
+    line.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use log::Level;
+    use serde_json::Value;
+
+    use super::*;
+
+    fn line(key_values: &[(&str, &str)]) -> Value {
+        let line = json_line(
+            &Record::builder()
+                .args(format_args!("Updated esl 0001"))
+                .level(Level::Info)
+                .module_path(Some("esl_services_backend::services::poll"))
+                .line(Some(42))
+                .key_values(&key_values)
+                .build(),
+            "2024-01-01T10:00:00.000+01:00",
+            "store-1",
+            &context(
+                &Record::builder()
+                    .args(format_args!(""))
+                    .key_values(&key_values)
+                    .build(),
+            ),
+        );
+        serde_json::from_str(&line).unwrap()
+    }
+
+    #[test]
+    fn adds_the_context_to_the_line() {
+        let line = line(&[("barcode", "0001"), ("requestId", "42")]);
+        assert_eq!(line["message"], "Updated esl 0001");
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["line"], 42);
+        assert_eq!(line["client_serial"], "store-1");
+        assert_eq!(line["barcode"], "0001");
+        assert_eq!(line["requestId"], "42");
+    }
+
+    #[test]
+    fn the_context_cannot_override_the_reserved_keys() {
+        let line = line(&[
+            ("message", "spoofed"),
+            ("level", "ERROR"),
+            ("timestamp", "never"),
+            ("client_serial", "other"),
+            ("barcode", "0001"),
+        ]);
+        assert_eq!(line["message"], "Updated esl 0001");
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["timestamp"], "2024-01-01T10:00:00.000+01:00");
+        assert_eq!(line["client_serial"], "store-1");
+        assert_eq!(line["barcode"], "0001");
+    }
+}
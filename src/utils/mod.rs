@@ -1 +1,9 @@
+pub mod backoff;
+pub mod circuit_breaker;
+pub mod log_file;
+pub mod log_filter;
+pub mod log_format;
+pub mod progress;
+pub mod retry;
+pub mod shutdown;
 pub mod unicode_string;
@@ -0,0 +1,14 @@
+/// Shows a message on the progress bar `$pb`, or logs it at `$level` when the bar is hidden, e.g.
+/// in headless mode. A macro so that the log keeps the module and line of the caller.
+macro_rules! status {
+    ($pb:expr, $level:expr, $($arg:tt)+) => {{
+        let pb: &indicatif::ProgressBar = $pb;
+        if pb.is_hidden() {
+            log::log!($level, $($arg)+);
+        } else {
+            pb.set_message(format!($($arg)+));
+        }
+    }};
+}
+
+pub(crate) use status;
@@ -0,0 +1,58 @@
+use std::{future::Future, time::Duration};
+
+use log::debug;
+use tokio::time::sleep;
+
+/// Upper bound of the delay between two attempts, before the jitter
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// How many times, and how far apart, a failing call is attempted
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    /// Delay before the second attempt, doubled after every attempt
+    pub base_delay: Duration,
+    /// Maximum random delay added to every wait, so that clients do not retry in lockstep
+    pub jitter: Duration,
+}
+
+impl RetryPolicy {
+    /// Returns the delay to wait after the failed `attempt`, starting at 1
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(MAX_DELAY);
+        let jitter = fastrand::u64(0..=self.jitter.as_millis() as u64);
+        exponential + Duration::from_millis(jitter)
+    }
+}
+
+/// Calls `f` until it succeeds, fails with an error that `is_retryable` rejects or
+/// `policy.max_attempts` is reached.
+pub async fn retry<T, E, F, Fut>(
+    policy: &RetryPolicy,
+    is_retryable: impl Fn(&E) -> bool,
+    mut f: F,
+) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: std::fmt::Display,
+{
+    let mut attempt = 1;
+    loop {
+        match f().await {
+            Err(e) if attempt < policy.max_attempts && is_retryable(&e) => {
+                let delay = policy.delay(attempt);
+                debug!(
+                    "Attempt {}/{} failed, retrying in {:?}: {}",
+                    attempt, policy.max_attempts, delay, e
+                );
+                sleep(delay).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use std::future::pending;
+
+use log::error;
+
+/// Waits for SIGINT (Ctrl-C) or, on unix, SIGTERM and returns the name of the signal.
+///
+/// Never returns when the signal handlers cannot be installed, the broker then runs until it is killed.
+pub async fn signal() -> &'static str {
+    #[cfg(unix)]
+    let received = {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => tokio::select! {
+                result = tokio::signal::ctrl_c() => result.map(|_| "SIGINT"),
+                _ = terminate.recv() => Ok("SIGTERM"),
+            },
+            Err(e) => Err(e),
+        }
+    };
+    #[cfg(not(unix))]
+    let received = tokio::signal::ctrl_c().await.map(|_| "Ctrl-C");
+
+    match received {
+        Ok(signal) => signal,
+        Err(e) => {
+            error!("Cannot listen to the shutdown signals: {}", e);
+            pending().await
+        }
+    }
+}